@@ -0,0 +1,106 @@
+use crate::{Build, BuildError};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The contents of a configuration file read by [`Build::from_config_file`]
+///
+/// [`Build::from_config_file`]: struct.Build.html#method.from_config_file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    project_directory: Option<PathBuf>,
+    target_directory: Option<PathBuf>,
+    copy_all: bool,
+    copy_items: Option<Vec<PathBuf>>,
+    scripts: Vec<String>,
+    node_env: Option<String>,
+    npm_path: Option<PathBuf>,
+    auto_detect_manager: bool,
+    install: Install,
+}
+
+/// The `install` section of a configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Install {
+    never: bool,
+    fast_sync: bool,
+    ci_quiet: bool,
+    no_save: bool,
+    prefer_dedupe: bool,
+    foreground_scripts: bool,
+    max_sockets: Option<u32>,
+    install_strategy: Option<String>,
+    script_args: Vec<String>,
+}
+
+/// Reads the configuration file at `path` into a new `Build`, as TOML if
+/// its extension is `toml` and as JSON otherwise.
+pub(crate) fn read(path: &Path) -> Result<Build, BuildError> {
+    let invalid =
+        |source: Box<dyn std::error::Error + Send + Sync>| BuildError::InvalidConfigFile {
+            path: path.into(),
+            source,
+        };
+    let contents = fs::read_to_string(path).map_err(|err| invalid(err.into()))?;
+    let config: Config = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&contents).map_err(|err| invalid(err.into()))?
+    } else {
+        serde_json::from_str(&contents).map_err(|err| invalid(err.into()))?
+    };
+
+    if config.copy_all && config.copy_items.is_some() {
+        return Err(invalid("copy_all and copy_items cannot both be set".into()));
+    }
+
+    let mut build = Build::new();
+    if let Some(dir) = &config.project_directory {
+        build.project_directory(dir);
+    }
+    if let Some(dir) = &config.target_directory {
+        build.target_directory(dir);
+    }
+    if config.copy_all {
+        build.copy_all();
+    }
+    if let Some(items) = &config.copy_items {
+        build.copy_items(items);
+    }
+    for script in &config.scripts {
+        build.plan_script(script);
+    }
+    if let Some(node_env) = &config.node_env {
+        build.node_env(node_env);
+    }
+    if let Some(npm) = &config.npm_path {
+        build.npm_path(npm);
+    }
+    if config.auto_detect_manager {
+        build.auto_detect_manager();
+    }
+
+    let install = &config.install;
+    if install.never {
+        build.never_install();
+    }
+    if install.fast_sync {
+        build.fast_sync();
+    }
+    if install.ci_quiet {
+        build.ci_quiet();
+    }
+    if let Some(n) = install.max_sockets {
+        build.max_sockets(n);
+    }
+    if let Some(strategy) = &install.install_strategy {
+        build.install_strategy(strategy);
+    }
+    let script_args: Vec<_> = install.script_args.iter().map(String::as_str).collect();
+    build
+        .no_save(install.no_save)
+        .prefer_dedupe(install.prefer_dedupe)
+        .foreground_scripts(install.foreground_scripts)
+        .install_script_args(&script_args);
+    Ok(build)
+}
@@ -0,0 +1,573 @@
+use std::env::JoinPathsError;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// The error type for fallible operations on a [`Build`]
+///
+/// [`Build`]: struct.Build.html
+#[derive(Debug)]
+pub enum BuildError {
+    /// The project directory does not exist.
+    ProjectDirectoryNotFound(PathBuf),
+    /// The project directory does not contain a `package.json`.
+    MissingPackageJson(PathBuf),
+    /// The target directory differs from the project directory but no
+    /// items were selected for copying.
+    NothingToCopy,
+    /// An item selected for copying is an absolute path.
+    AbsoluteCopyItem(PathBuf),
+    /// The path of a file to inject is absolute or leaves the target
+    /// directory through `..`.
+    InjectedFileOutsideTarget(PathBuf),
+    /// A required environment variable, e.g. one referenced in a path, is
+    /// not set.
+    UndefinedEnvVar(String),
+    /// An npm configuration file passed to npm does not exist.
+    MissingConfigFile(PathBuf),
+    /// A directory added to `PATH` contains the path separator.
+    InvalidPath(JoinPathsError),
+    /// An item selected for copying does not exist in the project directory.
+    CopyItemNotFound(PathBuf),
+    /// Copying would place the target directory inside one of the copied
+    /// items, or overwrite the project directory itself.
+    DirectoriesOverlap {
+        /// The configured project directory
+        project: PathBuf,
+        /// The configured target directory
+        target: PathBuf,
+    },
+    /// `NODE_ENV` is not one of the accepted values in strict mode.
+    UnknownNodeEnv(String),
+    /// npm could not be found on this machine.
+    NpmNotFound {
+        /// The value of `PATH` npm was looked up in
+        path: Option<OsString>,
+        /// The other locations that were searched, in order
+        searched: Vec<PathBuf>,
+    },
+    /// The package manager pinned in the `packageManager` field of
+    /// `package.json` could not be found in `PATH`.
+    PackageManagerNotFound(String),
+    /// node could not be found on this machine, even though it is required.
+    NodeNotFound,
+    /// npm could not be started.
+    SpawnFailed(io::Error),
+    /// The environment variable configuring the timeout is not a number of
+    /// seconds.
+    InvalidTimeout {
+        /// The name of the environment variable
+        var: String,
+        /// The value of the environment variable
+        value: String,
+    },
+    /// An npm command did not finish within the configured timeout and was
+    /// killed.
+    TimedOut(Duration),
+    /// `npm ci` was selected but there is no lockfile to install from.
+    MissingLockfile(PathBuf),
+    /// The filesystem of the target directory has less free space than
+    /// required before installing.
+    InsufficientDiskSpace {
+        /// The free space in bytes
+        available: u64,
+        /// The required free space in bytes
+        required: u64,
+    },
+    /// `npm install` or `npm ci` finished with a non 0 exit code.
+    InstallFailed(ExitStatus),
+    /// A command registered with [`Build::additional_install`] finished
+    /// with a non 0 exit code.
+    ///
+    /// [`Build::additional_install`]: struct.Build.html#method.additional_install
+    AdditionalInstallFailed {
+        /// The program that was run
+        command: String,
+        /// The exit status of the program
+        status: ExitStatus,
+    },
+    /// Building some of the targets registered with [`Build::add_target`]
+    /// failed. Contains the names of the failed targets, the reasons are
+    /// in the [`Build::report`].
+    ///
+    /// [`Build::add_target`]: struct.Build.html#method.add_target
+    /// [`Build::report`]: struct.Build.html#method.report
+    TargetsFailed(Vec<String>),
+    /// `npm ls` found missing or invalid packages in `node_modules`. Contains
+    /// the problems npm reported.
+    BrokenInstall(String),
+    /// `node_modules` is larger than the configured limit after installing.
+    NodeModulesTooLarge {
+        /// The size of `node_modules` in bytes
+        actual: u64,
+        /// The configured limit in bytes
+        limit: u64,
+    },
+    /// The workspaces of the project depend on each other in a cycle,
+    /// listed with the first workspace repeated at the end.
+    DependencyCycle(Vec<String>),
+    /// A command line of script arguments has unbalanced quotes or a
+    /// trailing escape.
+    InvalidCommandLine(String),
+    /// An npm script finished with a non 0 exit code.
+    ScriptFailed {
+        /// The name of the script
+        script: String,
+        /// The exit status of npm
+        status: ExitStatus,
+    },
+    /// `npm outdated` found dependencies with newer versions available.
+    OutdatedDependencies(Vec<String>),
+    /// An npm script run for its output finished with a non 0 exit code.
+    ScriptOutputFailed {
+        /// The name of the script
+        script: String,
+        /// The exit status of npm
+        status: ExitStatus,
+        /// What the script printed to stderr
+        stderr: String,
+    },
+    /// No script in `package.json` starts with the given prefix.
+    NoMatchingScripts(String),
+    /// Several scripts run in parallel failed.
+    ScriptsFailed(Vec<BuildError>),
+    /// Copying items in parallel failed. Contains the error of every item
+    /// that could not be copied.
+    CopyFailed(Vec<BuildError>),
+    /// An npm command other than install or a script, such as one run
+    /// through [`Build::npm_command`], finished with a non 0 exit code.
+    ///
+    /// [`Build::npm_command`]: struct.Build.html#method.npm_command
+    CommandFailed {
+        /// The npm subcommand
+        subcommand: String,
+        /// The exit status of npm
+        status: ExitStatus,
+    },
+    /// A `package.json` could not be read.
+    CannotReadManifest {
+        /// The path of the manifest
+        path: PathBuf,
+        /// The underlying io error
+        source: io::Error,
+    },
+    /// A modified `package.json`, the build manifest or the dependency
+    /// list could not be written.
+    CannotWriteManifest {
+        /// The path of the manifest
+        path: PathBuf,
+        /// The underlying io error
+        source: io::Error,
+    },
+    /// A `package.json` is not valid JSON.
+    InvalidManifest {
+        /// The path of the manifest
+        path: PathBuf,
+        /// The underlying parse error
+        source: serde_json::Error,
+    },
+    /// The `type` field of `package.json` does not match the required module
+    /// type.
+    ModuleTypeMismatch {
+        /// The required module type
+        expected: String,
+        /// The module type of the project, `commonjs` if `type` is not set
+        actual: String,
+    },
+    /// Direct dependencies in `package.json` are missing from
+    /// `package-lock.json` or locked with a different version range.
+    LockfileOutOfSync(Vec<String>),
+    /// The installed node does not match the version pinned in `.nvmrc` or
+    /// `.node-version`.
+    NodeVersionMismatch {
+        /// The pinned version
+        expected: String,
+        /// The output of `node --version`
+        actual: String,
+    },
+    /// `node --version` could not be run.
+    NodeVersionUnavailable(io::Error),
+    /// The required npm version is not a valid semver requirement.
+    InvalidVersionRequirement {
+        /// The requirement as given
+        requirement: String,
+        /// The underlying parse error
+        source: semver::Error,
+    },
+    /// The installed npm does not satisfy the required version.
+    NpmVersionTooOld {
+        /// The version requirement
+        required: String,
+        /// The output of `npm --version`
+        actual: String,
+    },
+    /// The target directory could not be created.
+    CannotCreateTarget {
+        /// The configured target directory
+        path: PathBuf,
+        /// The underlying io error
+        source: io::Error,
+    },
+    /// An output to be copied back from the temporary target directory was
+    /// not produced by the build.
+    MissingOutput(PathBuf),
+    /// No file in the target directory matches a pattern of expected
+    /// outputs.
+    NoOutputMatched(String),
+    /// A pattern of expected outputs is not a valid glob.
+    InvalidOutputGlob {
+        /// The pattern
+        pattern: String,
+        /// The underlying parse error
+        source: glob::PatternError,
+    },
+    /// The outputs of a script do not hash to the expected digest.
+    OutputHashMismatch {
+        /// The expected SHA-256 digest
+        expected: String,
+        /// The SHA-256 digest of the outputs
+        actual: String,
+    },
+    /// An output hash is asserted but no outputs are declared to hash.
+    NoOutputsToHash,
+    /// A file of environment variables written by a script is not a JSON
+    /// object of single line strings, numbers and booleans.
+    InvalidEnvOutput(PathBuf),
+    /// Reading files in the project or target directory failed.
+    Io(io::Error),
+    /// Copying items to the target directory failed.
+    Copy(fs_extra::error::Error),
+    /// Files of the copied items are missing in the target directory after
+    /// copying.
+    CopyIncomplete(Vec<PathBuf>),
+    /// A file to log npm output to could not be opened.
+    CannotOpenLog {
+        /// The path of the log file
+        path: PathBuf,
+        /// The underlying io error
+        source: io::Error,
+    },
+    /// Running npm in a pseudo terminal failed.
+    #[cfg(feature = "pty")]
+    Pty(Box<dyn Error + Send + Sync>),
+    /// An env file could not be read or is malformed.
+    #[cfg(feature = "dotenv")]
+    InvalidEnvFile {
+        /// The path of the env file
+        path: PathBuf,
+        /// The underlying parse or io error
+        source: dotenvy::Error,
+    },
+    /// A configuration file could not be read, is malformed or contains
+    /// unknown keys.
+    #[cfg(feature = "config")]
+    InvalidConfigFile {
+        /// The path of the configuration file
+        path: PathBuf,
+        /// The underlying io or parse error
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ProjectDirectoryNotFound(path) => {
+                write!(f, "Project directory {} does not exist", path.display())
+            }
+            Self::MissingPackageJson(path) => {
+                write!(f, "No package.json found in {}", path.display())
+            }
+            Self::NothingToCopy => {
+                write!(f, "Target directory selected but no items to copy there")
+            }
+            Self::AbsoluteCopyItem(path) => write!(
+                f,
+                "Items to be copied cannot be absolute paths: {}",
+                path.display()
+            ),
+            Self::InjectedFileOutsideTarget(path) => write!(
+                f,
+                "Injected files must be relative paths inside the target directory: {}",
+                path.display()
+            ),
+            Self::UndefinedEnvVar(name) => {
+                write!(f, "Environment variable {} is not set", name)
+            }
+            Self::MissingConfigFile(path) => {
+                write!(f, "Npm config file {} does not exist", path.display())
+            }
+            Self::InvalidPath(err) => write!(f, "Could not extend PATH: {}", err),
+            Self::CopyItemNotFound(path) => {
+                write!(f, "Item to be copied does not exist: {}", path.display())
+            }
+            Self::DirectoriesOverlap { project, target } => write!(
+                f,
+                "Target directory {} overlaps with project directory {}",
+                target.display(),
+                project.display()
+            ),
+            Self::UnknownNodeEnv(value) => write!(f, "Unknown NODE_ENV value {}", value),
+            Self::NpmNotFound { path, searched } => {
+                let executable = if cfg!(windows) { "npm.cmd" } else { "npm" };
+                match path {
+                    Some(path) => write!(
+                        f,
+                        "Could not find npm installation, {} is not in PATH ({})",
+                        executable,
+                        path.to_string_lossy()
+                    )?,
+                    None => write!(
+                        f,
+                        "Could not find npm installation, PATH is not set"
+                    )?,
+                }
+                for (i, dir) in searched.iter().enumerate() {
+                    let separator = if i == 0 { " or in " } else { ", " };
+                    write!(f, "{}{}", separator, dir.display())?;
+                }
+                Ok(())
+            }
+            Self::PackageManagerNotFound(manager) => write!(
+                f,
+                "Could not find {}, which is set as packageManager in package.json, in PATH",
+                manager
+            ),
+            Self::NodeNotFound => write!(
+                f,
+                "Could not find node installation, make sure node is installed and in PATH"
+            ),
+            Self::SpawnFailed(err) => write!(f, "Could not start npm: {}", err),
+            Self::InvalidTimeout { var, value } => write!(
+                f,
+                "Environment variable {} must be a number of seconds, not {}",
+                var, value
+            ),
+            Self::TimedOut(timeout) => write!(
+                f,
+                "Npm did not finish within {} seconds",
+                timeout.as_secs_f64()
+            ),
+            Self::MissingLockfile(dir) => write!(
+                f,
+                "No package-lock.json or npm-shrinkwrap.json in {} for npm ci, run npm install and commit the lockfile",
+                dir.display()
+            ),
+            Self::InsufficientDiskSpace {
+                available,
+                required,
+            } => write!(
+                f,
+                "Only {} bytes are free for installing node packages, {} bytes are required",
+                available, required
+            ),
+            Self::InstallFailed(status) => {
+                write!(
+                    f,
+                    "Npm install/ci failed with a non 0 exit code ({})",
+                    status
+                )
+            }
+            Self::AdditionalInstallFailed { command, status } => write!(
+                f,
+                "Install command {} finished with a non 0 exit code ({})",
+                command, status
+            ),
+            Self::TargetsFailed(targets) => {
+                write!(f, "Could not build targets {}", targets.join(", "))
+            }
+            Self::BrokenInstall(problems) => write!(
+                f,
+                "node_modules is incomplete or inconsistent, reinstall the packages: {}",
+                problems
+            ),
+            Self::NodeModulesTooLarge { actual, limit } => write!(
+                f,
+                "node_modules is {} bytes, exceeding the limit of {} bytes",
+                actual, limit
+            ),
+            Self::DependencyCycle(workspaces) => write!(
+                f,
+                "The workspaces depend on each other in a cycle: {}",
+                workspaces.join(" -> ")
+            ),
+            Self::InvalidCommandLine(cmdline) => {
+                write!(f, "Could not parse script arguments {:?}", cmdline)
+            }
+            Self::ScriptFailed { script, status } => write!(
+                f,
+                "Npm script {} finished with a non 0 exit code ({})",
+                script, status
+            ),
+            Self::OutdatedDependencies(packages) => {
+                write!(f, "Outdated npm dependencies: {}", packages.join(", "))
+            }
+            Self::ScriptOutputFailed {
+                script,
+                status,
+                stderr,
+            } => write!(
+                f,
+                "Npm script {} finished with a non 0 exit code ({}):\n{}",
+                script,
+                status,
+                stderr.trim_end()
+            ),
+            Self::NoMatchingScripts(prefix) => {
+                write!(f, "No npm script starts with {}", prefix)
+            }
+            Self::ScriptsFailed(errors) => {
+                write!(f, "{} npm scripts failed", errors.len())?;
+                for err in errors {
+                    write!(f, "\n  {}", err)?;
+                }
+                Ok(())
+            }
+            Self::CopyFailed(errors) => {
+                write!(f, "Could not copy {} items", errors.len())?;
+                for err in errors {
+                    write!(f, "\n  {}", err)?;
+                }
+                Ok(())
+            }
+            Self::CommandFailed { subcommand, status } => write!(
+                f,
+                "Npm {} finished with a non 0 exit code ({})",
+                subcommand, status
+            ),
+            Self::CannotReadManifest { path, source } => {
+                write!(f, "Could not read {}: {}", path.display(), source)
+            }
+            Self::CannotWriteManifest { path, source } => {
+                write!(f, "Could not write {}: {}", path.display(), source)
+            }
+            Self::InvalidManifest { path, source } => {
+                write!(f, "Could not parse {}: {}", path.display(), source)
+            }
+            Self::ModuleTypeMismatch { expected, actual } => write!(
+                f,
+                "package.json has module type {}, but {} is required",
+                actual, expected
+            ),
+            Self::LockfileOutOfSync(packages) => write!(
+                f,
+                "package-lock.json is out of sync with package.json for {}, run npm install to update it",
+                packages.join(", ")
+            ),
+            Self::NodeVersionMismatch { expected, actual } => write!(
+                f,
+                "Node version {} does not match the pinned version {}",
+                actual, expected
+            ),
+            Self::NodeVersionUnavailable(err) => {
+                write!(f, "Could not determine node version: {}", err)
+            }
+            Self::InvalidVersionRequirement {
+                requirement,
+                source,
+            } => write!(
+                f,
+                "Could not parse npm version requirement {}: {}",
+                requirement, source
+            ),
+            Self::NpmVersionTooOld { required, actual } => write!(
+                f,
+                "Npm version {} does not satisfy the required version {}",
+                actual, required
+            ),
+            Self::CannotCreateTarget { path, source } => write!(
+                f,
+                "Could not create target directory {}: {}",
+                path.display(),
+                source
+            ),
+            Self::MissingOutput(path) => {
+                write!(f, "Expected output {} was not produced", path.display())
+            }
+            Self::NoOutputMatched(pattern) => {
+                write!(f, "No build output matches {}", pattern)
+            }
+            Self::InvalidOutputGlob { pattern, source } => {
+                write!(f, "Invalid output pattern {}: {}", pattern, source)
+            }
+            Self::OutputHashMismatch { expected, actual } => write!(
+                f,
+                "Build outputs have hash {}, expected {}",
+                actual, expected
+            ),
+            Self::NoOutputsToHash => write!(
+                f,
+                "Cannot check the output hash, no build outputs are declared"
+            ),
+            Self::InvalidEnvOutput(path) => write!(
+                f,
+                "{} must contain a JSON object of single line strings, numbers and booleans",
+                path.display()
+            ),
+            Self::Io(err) => write!(f, "Could not read files: {}", err),
+            Self::Copy(err) => write!(f, "Could not copy items to target directory: {}", err),
+            Self::CopyIncomplete(files) => write!(
+                f,
+                "Could not copy every file to the target directory, missing {}",
+                files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::CannotOpenLog { path, source } => {
+                write!(f, "Could not open log file {}: {}", path.display(), source)
+            }
+            #[cfg(feature = "pty")]
+            Self::Pty(err) => write!(f, "Could not run npm in a pseudo terminal: {}", err),
+            #[cfg(feature = "dotenv")]
+            Self::InvalidEnvFile { path, source } => {
+                write!(f, "Could not load env file {}: {}", path.display(), source)
+            }
+            #[cfg(feature = "config")]
+            Self::InvalidConfigFile { path, source } => {
+                write!(f, "Could not load config file {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::SpawnFailed(err) | Self::NodeVersionUnavailable(err) | Self::Io(err) => Some(err),
+            Self::CannotCreateTarget { source, .. }
+            | Self::CannotOpenLog { source, .. }
+            | Self::CannotReadManifest { source, .. }
+            | Self::CannotWriteManifest { source, .. } => Some(source),
+            Self::InvalidManifest { source, .. } => Some(source),
+            Self::InvalidOutputGlob { source, .. } => Some(source),
+            Self::InvalidVersionRequirement { source, .. } => Some(source),
+            Self::InvalidPath(err) => Some(err),
+            Self::Copy(err) => Some(err),
+            #[cfg(feature = "pty")]
+            Self::Pty(err) => Some(err.as_ref()),
+            #[cfg(feature = "dotenv")]
+            Self::InvalidEnvFile { source, .. } => Some(source),
+            #[cfg(feature = "config")]
+            Self::InvalidConfigFile { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<fs_extra::error::Error> for BuildError {
+    fn from(err: fs_extra::error::Error) -> Self {
+        Self::Copy(err)
+    }
+}
@@ -0,0 +1,63 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Collects the files below `dir`, relative to `root`.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            files.push(path.strip_prefix(root).unwrap_or(&path).into());
+        }
+    }
+    Ok(())
+}
+
+/// Computes a SHA-256 digest over the files `outputs` relative to `dir`,
+/// including every file below those that are directories, and returns it
+/// as lowercase hex.
+///
+/// Files are hashed in order of their relative paths, each as its path with
+/// `/` separators, a 0 byte, its length as 8 little endian bytes and its
+/// contents.
+pub(crate) fn output_hash(dir: &Path, outputs: &[PathBuf]) -> io::Result<String> {
+    let mut files = Vec::new();
+    for output in outputs {
+        let path = dir.join(output);
+        if path.is_dir() {
+            collect_files(dir, &path, &mut files)?;
+        } else {
+            files.push(output.clone());
+        }
+    }
+    let mut files: Vec<_> = files
+        .into_iter()
+        .map(|file| {
+            let name = file
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            (name, file)
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let mut hasher = Sha256::new();
+    for (name, file) in files {
+        let contents = fs::read(dir.join(file))?;
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
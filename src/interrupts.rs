@@ -0,0 +1,103 @@
+use std::io;
+use std::process::{Child, Command};
+
+/// A running npm process that is terminated if the build script is
+/// interrupted. Stops being tracked when dropped.
+pub(crate) struct Tracked(#[cfg(unix)] Option<usize>);
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicI32 = AtomicI32::new(0);
+
+    /// The process groups of the running npm processes, 0 marks a free
+    /// slot. A fixed array because the signal handler cannot lock or
+    /// allocate.
+    pub(super) static GROUPS: [AtomicI32; 64] = [EMPTY; 64];
+
+    extern "C" fn terminate_groups(signal: libc::c_int) {
+        for group in &GROUPS {
+            let group = group.load(Ordering::SeqCst);
+            if group > 0 {
+                unsafe { libc::kill(-group, libc::SIGTERM) };
+            }
+        }
+        // Let the signal take its default effect on the build script
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub(super) fn install_handler() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            let handler = terminate_groups as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            unsafe {
+                libc::signal(libc::SIGINT, handler);
+                libc::signal(libc::SIGTERM, handler);
+            }
+        });
+    }
+
+    pub(super) fn track(pid: u32) -> Option<usize> {
+        GROUPS.iter().position(|group| {
+            group
+                .compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+    }
+}
+
+/// Starts `command` in its own process group, which is terminated when the
+/// build script receives SIGINT or SIGTERM.
+#[cfg(unix)]
+pub(crate) fn spawn(command: &mut Command) -> io::Result<(Child, Tracked)> {
+    unix::install_handler();
+    let child = spawn_group(command)?;
+    let slot = unix::track(child.id());
+    Ok((child, Tracked(slot)))
+}
+
+/// Starts `command` in its own process group, so [`kill`] reaches the
+/// processes it starts, without touching how signals are handled.
+#[cfg(unix)]
+pub(crate) fn spawn_group(command: &mut Command) -> io::Result<Child> {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0).spawn()
+}
+
+/// Starts `command`. Windows delivers Ctrl-C to every process attached to
+/// the console, so npm is interrupted together with the build script.
+#[cfg(not(unix))]
+pub(crate) fn spawn(command: &mut Command) -> io::Result<(Child, Tracked)> {
+    Ok((command.spawn()?, Tracked()))
+}
+
+/// Starts `command`. Process groups are a Unix concept.
+#[cfg(not(unix))]
+pub(crate) fn spawn_group(command: &mut Command) -> io::Result<Child> {
+    command.spawn()
+}
+
+/// Kills `child` and, on Unix, every process in its process group.
+pub(crate) fn kill(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+    child.kill()
+}
+
+#[cfg(unix)]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0 {
+            unix::GROUPS[slot].store(0, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
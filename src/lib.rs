@@ -30,46 +30,254 @@
 //!
 //! [`Build`]: struct.Build.html
 
+#[cfg(feature = "config")]
+mod config;
+mod error;
+mod hash;
+mod interrupts;
+mod limits;
+mod manifest;
+#[cfg(feature = "pty")]
+mod pty;
+mod report;
+mod target;
+
+pub use error::BuildError;
+pub use limits::ResourceLimits;
+pub use manifest::{package_name, scripts};
+pub use report::{CommandReport, RunReport, TargetReport};
+pub use target::Target;
+
+use fs2::FileExt;
 use fs_extra::{copy_items, dir::CopyOptions, remove_items};
+use interrupts::Tracked;
+use manifest::{
+    locked_packages, lockfile_drift, node_modules_in_sync, package_manager, read_manifest,
+    workspace_order, write_manifest,
+};
 use std::env;
-use std::fs::create_dir_all;
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
 use which::which;
 
-#[derive(PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 enum CopyItems {
     Nothing,
     All,
     Some(Vec<PathBuf>),
+    Sync(Vec<PathBuf>),
 }
 
+#[derive(Clone, Debug)]
 enum NodeEnv {
     Production,
     Development,
+    Test,
     Custom(String),
 }
 
+impl From<&str> for NodeEnv {
+    fn from(value: &str) -> Self {
+        match value {
+            "production" => NodeEnv::Production,
+            "development" => NodeEnv::Development,
+            "test" => NodeEnv::Test,
+            custom => NodeEnv::Custom(custom.into()),
+        }
+    }
+}
+
 impl NodeEnv {
     fn to_env_var(&self) -> &str {
         match self {
             Self::Production => "production",
             Self::Development => "development",
+            Self::Test => "test",
             Self::Custom(str) => str.as_ref(),
         }
     }
 }
 
+/// A script recorded for the next `execute`, with the arguments and
+/// `NODE_ENV` it is run with.
+#[derive(Clone, Debug)]
+struct PlannedScript {
+    name: String,
+    args: Vec<String>,
+    node_env: Option<NodeEnv>,
+    allow_failure: bool,
+}
+
+impl PlannedScript {
+    fn new(name: &str) -> Self {
+        PlannedScript {
+            name: name.into(),
+            args: Vec::new(),
+            node_env: None,
+            allow_failure: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Timeout {
+    Fixed(Duration),
+    FromEnv { var: String, default: Duration },
+}
+
+struct CommandHook(Box<dyn Fn(&mut Command)>);
+
+impl fmt::Debug for CommandHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CommandHook")
+    }
+}
+
+struct LineHook(Box<dyn FnMut(&str) + Send>);
+
+impl fmt::Debug for LineHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("LineHook")
+    }
+}
+
+type LineReader = thread::JoinHandle<io::Result<()>>;
+
+struct ManifestPatch(Box<dyn FnOnce(&mut serde_json::Value)>);
+
+impl fmt::Debug for ManifestPatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ManifestPatch")
+    }
+}
+
+/// A secret value that is redacted in `Debug` output.
+struct Secret<T = String>(T);
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
 /// A builder for an npm runner configuration
 ///
 /// A `Build` is the main type of the `npm-rs` crate and is used to control all
 /// configuration options.
+#[derive(Debug)]
 pub struct Build {
     project_directory: PathBuf,
     copy: CopyItems,
+    expand_env_in_paths: bool,
+    exclude_hidden: bool,
+    deterministic_order: bool,
+    copy_once: bool,
+    changed_since: Option<String>,
+    parallel_copy: bool,
+    verify_copy: bool,
+    overwrite_readonly: bool,
     target_directory: PathBuf,
+    files_prepared: bool,
     installed: bool,
+    install_disabled: bool,
+    install_skipped: bool,
+    fast_sync: bool,
+    verify_lockfile: bool,
+    require_lockfile: Option<bool>,
+    did_install: bool,
     node_env: NodeEnv,
+    strict_node_env: bool,
+    allowed_node_envs: Vec<String>,
+    skipped_node_envs: Vec<String>,
+    max_sockets: Option<u32>,
+    install_script_args: Vec<String>,
+    prefer_dedupe: bool,
+    no_progress: bool,
+    no_audit: bool,
+    no_fund: bool,
+    no_save: bool,
+    foreground_scripts: bool,
+    ci_fallback: bool,
+    install_strategy: Option<String>,
+    auth_tokens: Vec<(String, Secret)>,
+    strict_ssl: bool,
+    tmp_dir: Option<PathBuf>,
+    home_dir: Option<PathBuf>,
+    dedupe: bool,
+    additional_installs: Vec<(String, Vec<String>)>,
+    max_node_modules_size: Option<u64>,
+    required_free_space: Option<u64>,
+    serialize_installs: bool,
+    fail_if_outdated: bool,
+    verify_install: bool,
+    emit_dependencies: bool,
+    dependency_list: Option<PathBuf>,
+    resource_limits: Option<ResourceLimits>,
+    umask: Option<u32>,
+    handle_interrupts: bool,
+    cleanup_on_drop: bool,
+    remove_source_after: Option<Vec<PathBuf>>,
+    temp_outputs: Option<Vec<PathBuf>>,
+    env_output: Option<PathBuf>,
+    output_hash: Option<String>,
+    output_globs: Vec<String>,
+    temp_target: Option<TempDir>,
+    exports: Vec<(Vec<PathBuf>, PathBuf)>,
+    copied: Vec<PathBuf>,
+    envs: Vec<(OsString, Secret<OsString>)>,
+    env_allowlist: Option<Vec<String>>,
+    path_prepend: Vec<PathBuf>,
+    version_env: Option<String>,
+    user_config: Option<PathBuf>,
+    global_config: Option<PathBuf>,
+    target_triple_env: Option<String>,
+    #[cfg(feature = "dotenv")]
+    env_files: Vec<PathBuf>,
+    npm: Option<PathBuf>,
+    detect_manager: bool,
+    manager: Option<String>,
+    npm_search_paths: Option<Vec<PathBuf>>,
+    lookup: fn(&str) -> Option<PathBuf>,
+    run_verb: Option<String>,
+    run_flags: Vec<String>,
+    shell: Option<PathBuf>,
+    allowed_exit_codes: Vec<i32>,
+    failure_script: Option<String>,
+    timeout: Option<Timeout>,
+    log: Option<(PathBuf, PathBuf)>,
+    log_opened: bool,
+    line_hook: Option<Arc<Mutex<LineHook>>>,
+    forward_output_lines: bool,
+    output_prefix: Option<String>,
+    #[cfg(feature = "pty")]
+    use_pty: bool,
+    check_node_version: bool,
+    npm_version: Option<String>,
+    require_node: bool,
+    module_type: Option<String>,
+    failed_scripts: Vec<(String, ExitStatus)>,
+    retries: u32,
+    clean_on_retry: bool,
+    attempts: u32,
+    planned: Vec<PlannedScript>,
+    targets: Vec<Target>,
+    target_reports: Vec<TargetReport>,
+    commands: Vec<CommandReport>,
+    report_timing: bool,
+    build_manifest: Option<PathBuf>,
+    command_hooks: Vec<CommandHook>,
+    manifest_patches: Vec<ManifestPatch>,
+    patched_manifest: Option<serde_json::Value>,
+    injected_files: Vec<(PathBuf, Vec<u8>)>,
 }
 
 impl Default for Build {
@@ -78,15 +286,21 @@ impl Default for Build {
     }
 }
 
+impl Drop for Build {
+    fn drop(&mut self) {
+        if self.cleanup_on_drop && self.target_is_removable() {
+            let _ = remove_items(&[&self.target_directory]);
+        }
+    }
+}
+
 fn is_release() -> bool {
     !cfg!(debug_assertions)
 }
 
 fn node_env() -> NodeEnv {
     match env::var("NODE_ENV") {
-        Ok(str) if str == "production" => NodeEnv::Production,
-        Ok(str) if str == "development" => NodeEnv::Development,
-        Ok(str) => NodeEnv::Custom(str),
+        Ok(value) => value.as_str().into(),
         Err(_) => {
             if is_release() {
                 NodeEnv::Production
@@ -97,39 +311,479 @@ fn node_env() -> NodeEnv {
     }
 }
 
-fn get_folder_contents(dir: &PathBuf) -> Vec<PathBuf> {
-    dir.read_dir()
-        .unwrap()
-        .map(Result::unwrap)
-        .filter_map(|read_dir| {
-            let name = read_dir.file_name();
-            if name != "node_modules" {
-                Some(PathBuf::from(name))
-            } else {
-                None
+/// Lists the entries of `dir` except `node_modules`, and except those whose
+/// name starts with a `.` if `skip_hidden` is set.
+fn get_folder_contents(dir: &Path, skip_hidden: bool) -> io::Result<Vec<PathBuf>> {
+    let mut contents = Vec::new();
+    for entry in dir.read_dir()? {
+        let name = entry?.file_name();
+        let hidden = name.to_string_lossy().starts_with('.');
+        if name != "node_modules" && !(skip_hidden && hidden) {
+            contents.push(PathBuf::from(name));
+        }
+    }
+    Ok(contents)
+}
+
+/// Opens a log file for appending, creating its parent directories. If
+/// `truncate` is set, existing contents are removed first.
+fn open_log(path: &Path, truncate: bool) -> Result<File, BuildError> {
+    let open = || {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        if truncate {
+            File::create(path)?;
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    };
+    open().map_err(|source| BuildError::CannotOpenLog {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Reads the node version pinned by `.nvmrc` or `.node-version` in
+/// `project`, if any.
+fn pinned_node_version(project: &Path) -> io::Result<Option<String>> {
+    for name in &[".nvmrc", ".node-version"] {
+        let path = project.join(name);
+        if path.is_file() {
+            let contents = fs::read_to_string(path)?;
+            let version = contents
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty() && !line.starts_with('#'));
+            return Ok(version.map(String::from));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks whether the installed node version `actual` satisfies the
+/// possibly partial version `expected`, e.g. `18` matches `v18.17.1`.
+fn node_version_matches(expected: &str, actual: &str) -> bool {
+    let expected: Vec<_> = expected.trim_start_matches('v').split('.').collect();
+    let actual: Vec<_> = actual.trim_start_matches('v').split('.').collect();
+    expected.len() <= actual.len() && expected.iter().zip(&actual).all(|(e, a)| e == a)
+}
+
+/// The total size of the files in `dir` and its subdirectories, without
+/// following symlinks.
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Quotes `arg` for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Creates a command running `command` through `shell -c`, with the same
+/// environment and working directory. If `clear_env` is set, the shell
+/// does not inherit the environment of the build script either.
+fn through_shell(shell: &Path, command: &Command, clear_env: bool) -> Command {
+    let line = iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut wrapped = Command::new(shell);
+    wrapped.arg("-c").arg(line);
+    if clear_env {
+        wrapped.env_clear();
+    }
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// Puts `prefix` and a space in front of every line of `data`.
+fn prefix_lines(prefix: &str, data: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        prefixed.extend_from_slice(prefix.as_bytes());
+        prefixed.push(b' ');
+        prefixed.extend_from_slice(line);
+    }
+    prefixed
+}
+
+/// Starts a thread passing each line of `pipe` to `hook`, if any, and
+/// writing it to `sink` with `prefix` in front if `forward` is set.
+fn read_lines<R: Read + Send + 'static>(
+    pipe: R,
+    mut sink: Box<dyn Write + Send>,
+    forward: bool,
+    prefix: Option<String>,
+    hook: Option<Arc<Mutex<LineHook>>>,
+) -> LineReader {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if forward {
+                match &prefix {
+                    Some(prefix) => sink.write_all(&prefix_lines(prefix, &line))?,
+                    None => sink.write_all(&line)?,
+                }
             }
-        })
-        .collect()
+            if let Some(hook) = &hook {
+                let text = String::from_utf8_lossy(&line);
+                let mut hook = hook.lock().unwrap_or_else(PoisonError::into_inner);
+                (hook.0)(text.trim_end_matches(['\r', '\n']));
+            }
+            line.clear();
+        }
+        Ok(())
+    })
+}
+
+/// Waits for `child`, killing it and its process group once `timeout` has
+/// passed.
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Result<ExitStatus, BuildError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().map_err(BuildError::SpawnFailed)? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            // The child may have exited just now, which is not an error
+            let _ = interrupts::kill(child);
+            let _ = child.wait();
+            return Err(BuildError::TimedOut(timeout));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Copies `outputs` from `from` to the same relative location in `to`,
+/// replacing existing files.
+fn copy_outputs(outputs: &[PathBuf], from: &Path, to: &Path) -> Result<(), BuildError> {
+    for output in outputs {
+        let source = from.join(output);
+        if !source.exists() {
+            return Err(BuildError::MissingOutput(output.clone()));
+        }
+        let dest = to.join(output);
+        let parent = dest.parent().unwrap_or(to);
+        create_dir_all(parent).map_err(|source| BuildError::CannotCreateTarget {
+            path: to.into(),
+            source,
+        })?;
+        remove_items(&[&dest])?;
+        copy_items(&[source], parent, &CopyOptions::new())?;
+    }
+    Ok(())
+}
+
+/// Sorts directories named after node versions like `v18.17.0`, newest
+/// first.
+fn sort_newest_first(dirs: &mut [PathBuf]) {
+    let version = |dir: &PathBuf| {
+        dir.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    dirs.sort_by_key(|dir| std::cmp::Reverse(version(dir)));
+}
+
+/// Looks up the executable `name` in `PATH`.
+fn lookup_in_path(name: &str) -> Option<PathBuf> {
+    which(name).ok()
+}
+
+/// Common install locations of npm that may be missing from `PATH`, e.g.
+/// when a build runs outside of an interactive shell.
+fn default_npm_search_paths() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        if let Some(symlink) = env::var_os("NVM_SYMLINK") {
+            dirs.push(symlink.into());
+        }
+        if let Some(program_files) = env::var_os("ProgramFiles") {
+            dirs.push(PathBuf::from(program_files).join("nodejs"));
+        }
+        if let Some(app_data) = env::var_os("APPDATA") {
+            dirs.push(PathBuf::from(app_data).join("npm"));
+        }
+    } else {
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let nvm = env::var_os("NVM_DIR")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".nvm")));
+        if let Some(nvm) = nvm {
+            if let Ok(entries) = nvm.join("versions").join("node").read_dir() {
+                let mut versions: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+                sort_newest_first(&mut versions);
+                dirs.extend(versions.into_iter().map(|version| version.join("bin")));
+            }
+        }
+        if let Some(home) = &home {
+            dirs.push(home.join(".volta").join("bin"));
+        }
+        dirs.push("/usr/local/bin".into());
+        dirs.push("/opt/homebrew/bin".into());
+    }
+    dirs
 }
 
-fn copy_to_target(config: &CopyItems, from: &PathBuf, to: &PathBuf) {
+fn or_current_dir(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.into())
+}
+
+/// Checks whether copying `items` would either place one directory inside
+/// a copied item or remove part of the other directory.
+fn directories_overlap(project: &Path, target: &Path, items: &[PathBuf]) -> bool {
+    let project = absolute_path(project);
+    let target = absolute_path(target);
+    let contains = |outer: &Path, inner: &Path| match inner.strip_prefix(outer) {
+        Ok(relative) => items.iter().any(|item| relative.starts_with(item)),
+        Err(_) => false,
+    };
+    contains(&project, &target) || contains(&target, &project)
+}
+
+/// Replaces `${NAME}` references in `path` with the value of the environment
+/// variable `NAME`.
+fn expand_env_vars(path: &Path) -> Result<PathBuf, BuildError> {
+    let mut rest = match path.to_str() {
+        Some(path) => path,
+        None => return Ok(path.into()),
+    };
+    let mut expanded = String::new();
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        let value = env::var(name).map_err(|_| BuildError::UndefinedEnvVar(name.into()))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded.into())
+}
+
+/// Makes `path` and everything below it writable by its owner.
+fn clear_readonly(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(permissions.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    if metadata.is_dir() {
+        for entry in path.read_dir()? {
+            clear_readonly(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the items selected by `config` from `from` to `to` and returns
+/// the names of the copied entries in `to`. With `parallel` each item is
+/// copied on its own thread, with `overwrite_readonly` read-only files in
+/// the way are made writable first.
+fn copy_to_target(
+    config: &CopyItems,
+    from: &Path,
+    to: &Path,
+    parallel: bool,
+    overwrite_readonly: bool,
+) -> Result<Vec<PathBuf>, BuildError> {
     let item_list = match config {
-        CopyItems::Nothing => panic!("Target directory selected but no items to copy there"),
-        CopyItems::All => get_folder_contents(from),
-        CopyItems::Some(items) => items.clone(),
+        CopyItems::Nothing => return Err(BuildError::NothingToCopy),
+        CopyItems::All => get_folder_contents(from, false)?,
+        CopyItems::Some(items) | CopyItems::Sync(items) => items.clone(),
     };
-    for item in &item_list {
-        if item.is_absolute() {
-            panic!("Items to be copied cannot be absolute paths");
+    if let Some(item) = item_list.iter().find(|item| item.is_absolute()) {
+        return Err(BuildError::AbsoluteCopyItem(item.clone()));
+    }
+    if overwrite_readonly {
+        for item in &item_list {
+            clear_readonly(&to.join(item))?;
+        }
+    }
+    if let CopyItems::Sync(_) = config {
+        let keep: Vec<_> = item_list.iter().filter_map(|p| p.file_name()).collect();
+        let stale: Vec<_> = get_folder_contents(to, false)?
+            .into_iter()
+            .filter(|entry| !keep.contains(&entry.as_os_str()))
+            .map(|entry| to.join(entry))
+            .collect();
+        remove_items(&stale)?;
+    }
+    if parallel {
+        let copying: Vec<_> = item_list
+            .iter()
+            .map(|item| {
+                let (from, to) = (from.to_path_buf(), to.to_path_buf());
+                let item = item.clone();
+                thread::spawn(move || -> Result<(), BuildError> {
+                    remove_items(&[to.join(&item)])?;
+                    copy_items(&[from.join(&item)], &to, &CopyOptions::new())?;
+                    Ok(())
+                })
+            })
+            .collect();
+        // Wait for every item before reporting the errors, so no copy is
+        // still running when this returns
+        let errors: Vec<_> = copying
+            .into_iter()
+            .filter_map(|copying| copying.join().expect("Thread copying items panicked").err())
+            .collect();
+        if !errors.is_empty() {
+            return Err(BuildError::CopyFailed(errors));
+        }
+    } else {
+        remove_items(&item_list.iter().map(|p| to.join(p)).collect::<Vec<_>>())?;
+        copy_items(
+            &item_list.iter().map(|p| from.join(p)).collect::<Vec<_>>(),
+            to,
+            &CopyOptions::new(),
+        )?;
+    }
+    Ok(item_list
+        .iter()
+        .filter_map(|p| p.file_name())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Whether the output of a failed `npm ci` blames the lockfile, see
+/// [`Build::ci_fallback_to_install`].
+///
+/// [`Build::ci_fallback_to_install`]: struct.Build.html#method.ci_fallback_to_install
+fn ci_lockfile_error(output: &Output) -> bool {
+    [&output.stdout, &output.stderr].iter().any(|text| {
+        String::from_utf8_lossy(text)
+            .contains("can only install packages when your package.json and package-lock.json")
+    })
+}
+
+/// Lists the files below the copied `items` that are missing in `to`,
+/// relative to `to`.
+fn missing_copies(items: &[PathBuf], from: &Path, to: &Path) -> io::Result<Vec<PathBuf>> {
+    fn walk(source: &Path, copy: &Path, missing: &mut Vec<PathBuf>) -> io::Result<()> {
+        if source.is_dir() {
+            for entry in source.read_dir()? {
+                let name = entry?.file_name();
+                walk(&source.join(&name), &copy.join(&name), missing)?;
+            }
+        } else {
+            missing.push(copy.into());
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for item in items {
+        if let Some(name) = item.file_name() {
+            walk(&from.join(item), Path::new(name), &mut files)?;
         }
     }
-    remove_items(&item_list.iter().map(|p| to.join(p)).collect()).unwrap();
-    copy_items(
-        &item_list.iter().map(|p| from.join(p)).collect(),
-        to,
-        &CopyOptions::new(),
+    files.retain(|file| fs::symlink_metadata(to.join(file)).is_err());
+    Ok(files)
+}
+
+/// Lists the files below `dir` that differ from `git_ref`, relative to
+/// `dir`, or `None` if git cannot tell, e.g. outside of a repository.
+fn changed_files(dir: &Path, git_ref: &str) -> Option<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .args([
+            "diff",
+            "--name-only",
+            "--relative",
+            "-z",
+            git_ref,
+            "--",
+            ".",
+        ])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|file| !file.is_empty())
+            .map(PathBuf::from)
+            .collect(),
     )
-    .unwrap();
+}
+
+/// Copies the `changed` files from `from` to the same place in `to`,
+/// removing those deleted in `from`, and returns the copied files.
+/// `node_modules` is skipped.
+fn copy_changed(changed: &[PathBuf], from: &Path, to: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut copied = Vec::new();
+    for file in changed {
+        if file.starts_with("node_modules") {
+            continue;
+        }
+        let dest = to.join(file);
+        if from.join(file).is_file() {
+            if let Some(parent) = dest.parent() {
+                create_dir_all(parent)?;
+            }
+            fs::copy(from.join(file), &dest)?;
+            copied.push(file.clone());
+        } else if dest.is_file() {
+            fs::remove_file(&dest)?;
+        }
+    }
+    Ok(copied)
 }
 
 impl Build {
@@ -143,132 +797,4468 @@ impl Build {
         Build {
             project_directory: "".into(),
             copy: CopyItems::Nothing,
+            expand_env_in_paths: false,
+            exclude_hidden: false,
+            deterministic_order: true,
+            copy_once: false,
+            changed_since: None,
+            parallel_copy: false,
+            verify_copy: false,
+            overwrite_readonly: false,
             target_directory: "".into(),
+            files_prepared: false,
             installed: false,
+            install_disabled: false,
+            install_skipped: false,
+            fast_sync: false,
+            verify_lockfile: false,
+            require_lockfile: None,
+            did_install: false,
             node_env: node_env(),
+            strict_node_env: false,
+            allowed_node_envs: Vec::new(),
+            skipped_node_envs: Vec::new(),
+            max_sockets: None,
+            install_script_args: Vec::new(),
+            prefer_dedupe: false,
+            no_progress: false,
+            no_audit: false,
+            no_fund: false,
+            no_save: false,
+            foreground_scripts: false,
+            ci_fallback: false,
+            install_strategy: None,
+            auth_tokens: Vec::new(),
+            strict_ssl: true,
+            tmp_dir: None,
+            home_dir: None,
+            dedupe: false,
+            additional_installs: Vec::new(),
+            max_node_modules_size: None,
+            required_free_space: None,
+            serialize_installs: false,
+            fail_if_outdated: false,
+            verify_install: false,
+            emit_dependencies: false,
+            dependency_list: None,
+            resource_limits: None,
+            umask: None,
+            handle_interrupts: false,
+            cleanup_on_drop: false,
+            remove_source_after: None,
+            temp_outputs: None,
+            env_output: None,
+            output_hash: None,
+            output_globs: Vec::new(),
+            temp_target: None,
+            exports: Vec::new(),
+            copied: Vec::new(),
+            envs: Vec::new(),
+            env_allowlist: None,
+            path_prepend: Vec::new(),
+            version_env: None,
+            user_config: None,
+            global_config: None,
+            target_triple_env: None,
+            #[cfg(feature = "dotenv")]
+            env_files: Vec::new(),
+            npm: None,
+            detect_manager: false,
+            manager: None,
+            npm_search_paths: None,
+            lookup: lookup_in_path,
+            run_verb: Some("run".into()),
+            run_flags: Vec::new(),
+            shell: None,
+            allowed_exit_codes: Vec::new(),
+            failure_script: None,
+            timeout: None,
+            log: None,
+            log_opened: false,
+            line_hook: None,
+            forward_output_lines: true,
+            output_prefix: None,
+            #[cfg(feature = "pty")]
+            use_pty: false,
+            check_node_version: false,
+            npm_version: None,
+            require_node: false,
+            module_type: None,
+            failed_scripts: Vec::new(),
+            retries: 0,
+            clean_on_retry: false,
+            attempts: 0,
+            planned: Vec::new(),
+            targets: Vec::new(),
+            target_reports: Vec::new(),
+            commands: Vec::new(),
+            report_timing: false,
+            build_manifest: None,
+            command_hooks: Vec::new(),
+            manifest_patches: Vec::new(),
+            patched_manifest: None,
+            injected_files: Vec::new(),
         }
     }
 
+    /// Read a `Build` from the configuration file at `path`, to share
+    /// build settings between projects or keep them out of `build.rs`.
+    ///
+    /// The file is read as TOML if its extension is `toml`, and as JSON
+    /// otherwise. Every key is optional:
+    ///
+    /// ```toml
+    /// project_directory = "frontend"
+    /// target_directory = "target/frontend"
+    /// copy_all = true               # or copy_items = ["src", "package.json"]
+    /// scripts = ["build"]           # planned, run by execute()
+    /// node_env = "production"
+    /// npm_path = "/usr/local/bin/npm"
+    /// auto_detect_manager = true
+    ///
+    /// [install]
+    /// never = false
+    /// fast_sync = true
+    /// ci_quiet = true
+    /// no_save = true
+    /// prefer_dedupe = false
+    /// foreground_scripts = false
+    /// max_sockets = 8
+    /// install_strategy = "nested"
+    /// script_args = ["--verbose"]
+    /// ```
+    ///
+    /// Each key calls the method of the same name, the scripts are
+    /// recorded with [`plan_script`] and run by [`execute`]. Relative
+    /// directories are relative to the current directory, not to the file.
+    /// The returned `Build` can be configured further as usual.
+    ///
+    /// Requires the `config` feature.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BuildError::InvalidConfigFile`] if the file cannot be
+    /// read, is malformed or contains unknown keys, which catches typos,
+    /// or if it sets both `copy_all` and `copy_items`.
+    ///
+    /// [`plan_script`]: struct.Build.html#method.plan_script
+    /// [`execute`]: struct.Build.html#method.execute
+    /// [`BuildError::InvalidConfigFile`]: enum.BuildError.html#variant.InvalidConfigFile
+    #[cfg(feature = "config")]
+    pub fn from_config_file<P: AsRef<Path>>(path: P) -> Result<Self, BuildError> {
+        config::read(path.as_ref())
+    }
+
     /// Set the `NODE_ENV` environment variable. If this function is not
     /// called the `NODE_ENV` defaults to
     /// * `production` if building with `--release`
     /// * `development` otherwise
     pub fn node_env(&mut self, value: &str) -> &mut Self {
-        self.node_env = match value {
-            "production" => NodeEnv::Production,
-            "development" => NodeEnv::Development,
-            custom => NodeEnv::Custom(custom.into()),
-        };
+        self.node_env = value.into();
         self
     }
 
-    /// Set the target directory. Defaults to the current directory, which
-    /// for a build script is the directory the `build.rs` resides in.
-    /// This is the directory the npm project gets copied to.
-    ///
-    /// If you set this to something other than the [`project_directory`],
-    /// you must call either [`copy_items`] or [`copy_all`] to tell the
-    /// `Build` how to move the project files.
+    /// Set the `NODE_ENV` environment variable to `test`, for builds whose
+    /// scripts should behave as in a test run.
     ///
-    /// [`project_directory`]: struct.Build.html#method.project_directory
-    /// [`copy_items`]: struct.Build.html#method.copy_items
-    /// [`copy_all`]: struct.Build.html#method.copy_all
-    pub fn target_directory<P: AsRef<Path>>(&mut self, directory: P) -> &mut Self {
-        self.target_directory = directory.as_ref().into();
-        self.installed = false;
+    /// Cargo does not tell build scripts whether the crate is being built
+    /// for `cargo test`, so this is never selected by default.
+    pub fn test_env(&mut self) -> &mut Self {
+        self.node_env = NodeEnv::Test;
         self
     }
 
-    /// Sets the project directory, which is where the source of the npm
-    /// project is located. Defaults to the current directory, which for
-    /// a build script is the directory the `build.rs` resides in.
-    pub fn project_directory<P: AsRef<Path>>(&mut self, directory: P) -> &mut Self {
-        self.project_directory = directory.as_ref().into();
-        self.installed = false;
+    /// Do nothing if the effective `NODE_ENV` is `env`, e.g. `test` to
+    /// avoid building assets during tests. Can be called multiple times.
+    ///
+    /// Skipping means nothing is copied, installed or run, and running a
+    /// script or npm command succeeds immediately. [`run_script_output`]
+    /// returns an empty string.
+    ///
+    /// [`run_script_output`]: struct.Build.html#method.run_script_output
+    pub fn skip_if_node_env(&mut self, env: &str) -> &mut Self {
+        self.skipped_node_envs.push(env.into());
         self
     }
 
-    /// Tells the `Build` to copy the selected items from [`project_directory`]
-    /// to [`target_directory`]
+    fn is_skipped(&self, node_env: &NodeEnv) -> bool {
+        self.skipped_node_envs
+            .iter()
+            .any(|env| env == node_env.to_env_var())
+    }
+
+    /// Only accept `production`, `development`, `test` and values registered
+    /// with [`allow_node_env`] as `NODE_ENV`, failing with
+    /// [`BuildError::UnknownNodeEnv`] otherwise.
     ///
-    /// Has no effect if [`project_directory`] and [`target_directory`] are
-    /// the same.
+    /// This catches typos like `prodcution`, which are accepted silently by
+    /// default.
     ///
-    /// [`target_directory`]: struct.Build.html#method.target_directory
-    /// [`project_directory`]: struct.Build.html#method.project_directory
-    pub fn copy_items<L: IntoIterator<Item = P>, P: AsRef<Path>>(&mut self, items: L) -> &mut Self {
-        self.copy = CopyItems::Some(items.into_iter().map(|p| p.as_ref().into()).collect());
+    /// [`allow_node_env`]: struct.Build.html#method.allow_node_env
+    /// [`BuildError::UnknownNodeEnv`]: enum.BuildError.html#variant.UnknownNodeEnv
+    pub fn strict_node_env(&mut self) -> &mut Self {
+        self.strict_node_env = true;
         self
     }
 
-    /// Tells the `Build` to copy all files from [`project_directory`]
-    /// to [`target_directory`]
+    /// Register an additional `NODE_ENV` value accepted by
+    /// [`strict_node_env`], e.g. `staging`.
     ///
-    /// This does not copy the `node_modules` directory.
+    /// [`strict_node_env`]: struct.Build.html#method.strict_node_env
+    pub fn allow_node_env(&mut self, value: &str) -> &mut Self {
+        self.allowed_node_envs.push(value.into());
+        self
+    }
+
+    fn check_node_env(&self, node_env: &NodeEnv) -> Result<(), BuildError> {
+        match node_env {
+            NodeEnv::Custom(value) if self.strict_node_env => {
+                if self.allowed_node_envs.contains(value) {
+                    Ok(())
+                } else {
+                    Err(BuildError::UnknownNodeEnv(value.clone()))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Set an environment variable for the npm processes spawned by this
+    /// `Build`.
     ///
-    /// Has no effect if [`project_directory`] and [`target_directory`] are
-    /// the same.
+    /// `NODE_ENV` cannot be set this way, use [`node_env`] instead. The
+    /// value is redacted in the `Debug` output of the `Build`, as it may
+    /// be a secret.
     ///
-    /// [`target_directory`]: struct.Build.html#method.target_directory
-    /// [`project_directory`]: struct.Build.html#method.project_directory
-    pub fn copy_all(&mut self) -> &mut Self {
-        self.copy = CopyItems::All;
+    /// [`node_env`]: struct.Build.html#method.node_env
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.envs
+            .push((key.as_ref().to_owned(), Secret(value.as_ref().to_owned())));
         self
     }
 
-    /// Run an npm script with the given `script_name`.
+    /// Only pass the environment variables named in `keys` from the build
+    /// script to npm, instead of its whole environment, for more
+    /// reproducible builds.
     ///
-    /// Before running the script this function copies files from
-    /// [`project_directory`] to [`target_directory`] if necessary and
-    /// installs node packages.
+    /// Variables set by this `Build`, such as `NODE_ENV` and those set with
+    /// [`env`], are passed as well. `PATH` should usually be in the list,
+    /// otherwise npm may not find node. On Windows processes also tend to
+    /// need `SystemRoot`, and npm looks for its config in `USERPROFILE`
+    /// instead of `HOME`.
     ///
-    /// It uses `npm ci` if building with `--release`.
+    /// [`env`]: struct.Build.html#method.env
+    pub fn env_allowlist(&mut self, keys: &[&str]) -> &mut Self {
+        self.env_allowlist = Some(keys.iter().map(|key| key.to_string()).collect());
+        self
+    }
+
+    /// Set the npm configuration option `key`, e.g. `registry`, `cache` or
+    /// `strict-ssl`, for installing packages and running scripts.
     ///
-    /// # Panics
+    /// npm reads its configuration from environment variables named
+    /// `npm_config_` followed by the option, so this sets
+    /// `npm_config_<key>` with `key` lowercased and dashes replaced by
+    /// underscores. Like other environment variables it overrides `.npmrc`
+    /// files.
+    pub fn npm_config(&mut self, key: &str, value: &str) -> &mut Self {
+        let key = key.to_lowercase().replace('-', "_");
+        self.env(format!("npm_config_{}", key), value)
+    }
+
+    /// Use `path` as the user configuration file of npm instead of
+    /// `~/.npmrc`, for installing packages and running scripts.
     ///
-    /// * Panics if [`target_directory`] is different from [`project_directory`]
-    /// but neither [`copy_items`] nor [`copy_all`] was called.
-    /// * Panics if npm cannot be found on this machine.
-    /// * Panics if either `npm install`/`npm ci` returns with an error.
-    /// * Panics if the executed npm script returns with an error.
+    /// Like `--userconfig` this is passed as the `npm_config_userconfig`
+    /// environment variable, so npm commands run by scripts use it as well.
+    /// Running fails with [`BuildError::MissingConfigFile`] if the file does
+    /// not exist.
     ///
-    /// [`target_directory`]: struct.Build.html#method.target_directory
-    /// [`project_directory`]: struct.Build.html#method.project_directory
-    pub fn run_script(&mut self, script_name: &str) -> &mut Self {
-        let npm = which("npm").expect("Could not find npm installation");
+    /// [`BuildError::MissingConfigFile`]: enum.BuildError.html#variant.MissingConfigFile
+    pub fn user_config<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.user_config = Some(path.as_ref().into());
+        self
+    }
 
-        if !self.installed {
-            create_dir_all(&self.target_directory).expect("Could not create target directory");
-            if self.project_directory != self.target_directory {
-                copy_to_target(&self.copy, &self.project_directory, &self.target_directory);
-            }
+    /// Like [`user_config`], but sets the global configuration file of
+    /// npm, usually `$PREFIX/etc/npmrc`, through `npm_config_globalconfig`.
+    ///
+    /// [`user_config`]: struct.Build.html#method.user_config
+    pub fn global_config<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.global_config = Some(path.as_ref().into());
+        self
+    }
+
+    /// Pass the version of the crate being built to the npm processes as
+    /// the environment variable `CARGO_PKG_VERSION`, so the frontend can be
+    /// stamped with the same version.
+    ///
+    /// The version is read from the `CARGO_PKG_VERSION` variable Cargo sets
+    /// for build scripts. Use [`sync_version_as`] to choose a different
+    /// variable name. Note that npm overrides `npm_package_version` with the
+    /// version from `package.json`, so that name cannot be used.
+    ///
+    /// [`sync_version_as`]: struct.Build.html#method.sync_version_as
+    pub fn sync_version(&mut self) -> &mut Self {
+        self.sync_version_as("CARGO_PKG_VERSION")
+    }
 
-            let cmd = if is_release() { "ci" } else { "install" };
+    /// Like [`sync_version`], but sets the environment variable `name`.
+    ///
+    /// [`sync_version`]: struct.Build.html#method.sync_version
+    pub fn sync_version_as(&mut self, name: &str) -> &mut Self {
+        self.version_env = Some(name.into());
+        self
+    }
 
-            let npm_status = Command::new(&npm)
-                .env("NODE_ENV", NodeEnv::Development.to_env_var())
-                .arg(cmd)
-                .current_dir(&self.target_directory)
-                .status()
-                .expect("Could not run npm install/ci");
-            if !npm_status.success() {
-                panic!("Npm install/ci failed with a non 0 exit code");
-            }
+    /// Pass the target triple of the crate being built to the npm processes
+    /// as the environment variable `CARGO_TARGET`, e.g. to produce different
+    /// assets per platform when cross-compiling.
+    ///
+    /// The triple is read from the `TARGET` variable Cargo sets for build
+    /// scripts, running outside of Cargo fails with
+    /// [`BuildError::UndefinedEnvVar`]. Use [`sync_target_triple_as`] to
+    /// choose a different variable name.
+    ///
+    /// [`BuildError::UndefinedEnvVar`]: enum.BuildError.html#variant.UndefinedEnvVar
+    /// [`sync_target_triple_as`]: struct.Build.html#method.sync_target_triple_as
+    pub fn sync_target_triple(&mut self) -> &mut Self {
+        self.sync_target_triple_as("CARGO_TARGET")
+    }
 
-            self.installed = true;
-        }
+    /// Like [`sync_target_triple`], but sets the environment variable
+    /// `name`.
+    ///
+    /// [`sync_target_triple`]: struct.Build.html#method.sync_target_triple
+    pub fn sync_target_triple_as(&mut self, name: &str) -> &mut Self {
+        self.target_triple_env = Some(name.into());
+        self
+    }
+
+    /// Add `dir` to the front of `PATH` for the npm processes, e.g. to make
+    /// a binary built by Cargo available to npm scripts. Directories added
+    /// by earlier calls come first.
+    ///
+    /// The directories are prepended to the inherited `PATH`, or to the
+    /// value set with [`env`] if any, using the platform's separator.
+    ///
+    /// [`env`]: struct.Build.html#method.env
+    pub fn path_prepend<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.path_prepend.push(dir.as_ref().into());
+        self
+    }
 
-        let npm_status = Command::new(&npm)
-            .env("NODE_ENV", self.node_env.to_env_var())
-            .args(&["run", script_name])
-            .current_dir(&self.target_directory)
-            .status()
-            .expect("Could not start npm");
+    /// Load environment variables for the npm processes from a dotenv
+    /// file. Can be called multiple times, later files take precedence.
+    ///
+    /// The file is read when npm is run and follows the usual dotenv
+    /// syntax, including quoting and `#` comments. Variables set with
+    /// [`env`] take precedence over those loaded from a file.
+    ///
+    /// Requires the `dotenv` feature.
+    ///
+    /// [`env`]: struct.Build.html#method.env
+    #[cfg(feature = "dotenv")]
+    pub fn env_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.env_files.push(path.as_ref().into());
+        self
+    }
 
-        if npm_status.success() {
-            self
-        } else {
-            panic!("Npm finished with a non 0 exit code");
-        }
+    /// Never run `npm install`/`npm ci`, for projects whose packages are
+    /// installed by other means.
+    ///
+    /// Unlike the automatic install tracking this setting is not reset by
+    /// [`target_directory`] or [`project_directory`]. Files are still copied
+    /// to the target directory before the first run.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn never_install(&mut self) -> &mut Self {
+        self.install_disabled = true;
+        self
+    }
+
+    /// Skip `npm install`/`npm ci` before the next run, for example because
+    /// the packages in the target directory are known to be up to date.
+    ///
+    /// This only affects the next run that would install packages, but
+    /// unlike the automatic install tracking it is kept when
+    /// [`target_directory`] or [`project_directory`] is called afterwards.
+    /// Use [`never_install`] to disable installing for good.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`never_install`]: struct.Build.html#method.never_install
+    pub fn skip_install(&mut self) -> &mut Self {
+        self.install_skipped = true;
+        self
+    }
+
+    /// Check that `node` can be found in `PATH` before running anything.
+    ///
+    /// npm can be installed while node is missing, in which case npm fails
+    /// with a cryptic message. This check turns that into a
+    /// [`BuildError::NodeNotFound`].
+    ///
+    /// `node` is looked up in the `PATH` npm is run with, including
+    /// [`path_prepend`] and [`env`]. With a [`shell`] the check runs
+    /// `node --version` through the shell instead, as the shell may set up
+    /// its own `PATH`.
+    ///
+    /// [`BuildError::NodeNotFound`]: enum.BuildError.html#variant.NodeNotFound
+    /// [`path_prepend`]: struct.Build.html#method.path_prepend
+    /// [`env`]: struct.Build.html#method.env
+    /// [`shell`]: struct.Build.html#method.shell
+    pub fn require_node(&mut self) -> &mut Self {
+        self.require_node = true;
+        self
+    }
+
+    /// Check that the installed node matches the version pinned in the
+    /// `.nvmrc` or `.node-version` file of the [`project_directory`] before
+    /// running anything.
+    ///
+    /// Partial versions such as `18` or `v18.17` match any installed version
+    /// they are a prefix of. Aliases like `lts/*` cannot be checked and are
+    /// accepted, as is a project without a version file.
+    ///
+    /// The version is that of the `node` npm runs with, found in the `PATH`
+    /// set with [`path_prepend`] or [`env`], and run through the [`shell`]
+    /// if one is set.
+    ///
+    /// [`path_prepend`]: struct.Build.html#method.path_prepend
+    /// [`env`]: struct.Build.html#method.env
+    /// [`shell`]: struct.Build.html#method.shell
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn check_node_version_file(&mut self) -> &mut Self {
+        self.check_node_version = true;
+        self
+    }
+
+    /// Check that `npm --version` satisfies the semver requirement `req`,
+    /// e.g. `>=9.4`, before installing packages, failing with
+    /// [`BuildError::NpmVersionTooOld`] otherwise. This catches CI runners
+    /// with an npm too old for options like [`install_strategy`].
+    ///
+    /// The check runs once, with the executable that runs npm commands,
+    /// so with [`auto_detect_manager`] a detected yarn or pnpm is checked
+    /// instead. An invalid requirement fails with
+    /// [`BuildError::InvalidVersionRequirement`].
+    ///
+    /// [`install_strategy`]: struct.Build.html#method.install_strategy
+    /// [`auto_detect_manager`]: struct.Build.html#method.auto_detect_manager
+    /// [`BuildError::NpmVersionTooOld`]: enum.BuildError.html#variant.NpmVersionTooOld
+    /// [`BuildError::InvalidVersionRequirement`]: enum.BuildError.html#variant.InvalidVersionRequirement
+    pub fn require_npm_version(&mut self, req: &str) -> &mut Self {
+        self.npm_version = Some(req.into());
+        self
+    }
+
+    /// Skip installing if the packages in `node_modules` already match the
+    /// lockfile, and prefer cached packages when installing is needed.
+    ///
+    /// The installation is considered up to date if the hidden lockfile npm
+    /// writes to `node_modules/.package-lock.json` lists the packages and
+    /// versions of `package-lock.json` and no others. Optional packages,
+    /// which npm skips on platforms they do not support, may be missing
+    /// from it. Otherwise packages are
+    /// installed with `npm ci --prefer-offline`, or `npm install
+    /// --prefer-offline` if there is no lockfile. Requires npm 7 or later,
+    /// with older versions packages are always installed.
+    pub fn fast_sync(&mut self) -> &mut Self {
+        self.fast_sync = true;
+        self
+    }
+
+    /// Require the `type` field of the `package.json` in the
+    /// [`project_directory`] to be `ty`, usually `module` or `commonjs`,
+    /// failing with [`BuildError::ModuleTypeMismatch`] before anything is
+    /// copied or installed otherwise.
+    ///
+    /// A missing `type` counts as `commonjs`, like in node. This catches a
+    /// changed module type early, instead of through confusing errors deep
+    /// in the bundler.
+    ///
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`BuildError::ModuleTypeMismatch`]: enum.BuildError.html#variant.ModuleTypeMismatch
+    pub fn require_module_type(&mut self, ty: &str) -> &mut Self {
+        self.module_type = Some(ty.into());
+        self
+    }
+
+    /// Check that `package-lock.json` matches `package.json` before
+    /// installing packages, failing with [`BuildError::LockfileOutOfSync`]
+    /// naming the drifting packages instead of npm's generic error.
+    ///
+    /// Every direct dependency must be locked, with the same version range
+    /// for lockfiles written by npm 7 or later. Nothing is checked if there
+    /// is no lockfile.
+    ///
+    /// [`BuildError::LockfileOutOfSync`]: enum.BuildError.html#variant.LockfileOutOfSync
+    pub fn verify_lockfile_sync(&mut self) -> &mut Self {
+        self.verify_lockfile = true;
+        self
+    }
+
+    /// Decide what happens if `npm ci` is selected, when building with
+    /// `--release`, but the project has no `package-lock.json` or
+    /// `npm-shrinkwrap.json`.
+    ///
+    /// If `required` is `true` installing fails with
+    /// [`BuildError::MissingLockfile`], which explains how to fix it. If it
+    /// is `false`, `npm install` is used instead. If this function is not
+    /// called `npm ci` runs anyway and fails with npm's own error.
+    ///
+    /// [`BuildError::MissingLockfile`]: enum.BuildError.html#variant.MissingLockfile
+    pub fn require_lockfile(&mut self, required: bool) -> &mut Self {
+        self.require_lockfile = Some(required);
+        self
+    }
+
+    fn has_lockfile(&self) -> bool {
+        let target = self.effective_target();
+        let lockfiles: &[&str] = match self.manager_name() {
+            "yarn" => &["yarn.lock"],
+            "pnpm" => &["pnpm-lock.yaml"],
+            _ => &["package-lock.json", "npm-shrinkwrap.json"],
+        };
+        lockfiles
+            .iter()
+            .any(|lockfile| target.join(lockfile).is_file())
+    }
+
+    /// Set the maximum number of connections npm opens per origin while
+    /// installing packages, passed as `--maxsockets`. If this function is
+    /// not called npm uses its own default of 15.
+    ///
+    /// Raising this can speed up installs on fast networks, lowering it
+    /// helps on constrained ones. It does not affect running scripts.
+    pub fn max_sockets(&mut self, n: u32) -> &mut Self {
+        self.max_sockets = Some(n);
+        self
+    }
+
+    /// Forward `args` to the lifecycle scripts run while installing
+    /// packages, by appending `-- <args>` to `npm install`/`npm ci`.
+    ///
+    /// These arguments reach scripts such as `postinstall` or native module
+    /// builds, not npm itself.
+    pub fn install_script_args(&mut self, args: &[&str]) -> &mut Self {
+        self.install_script_args = args.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// Write the output of npm to files instead of inheriting the output of
+    /// the build script. Missing parent directories are created.
+    ///
+    /// The files are truncated when npm is first run by this `Build`, the
+    /// output of every later command is appended. `stdout` and `stderr`
+    /// may be the same file.
+    pub fn log_to<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, stdout: P, stderr: Q) -> &mut Self {
+        self.log = Some((stdout.as_ref().into(), stderr.as_ref().into()));
+        self.log_opened = false;
+        self
+    }
+
+    /// Pass `--no-progress` to `npm install`/`npm ci`, hiding the progress
+    /// bar that fills CI logs with control characters. Unlike a lower log
+    /// level this keeps warnings and errors.
+    pub fn no_progress(&mut self, enable: bool) -> &mut Self {
+        self.no_progress = enable;
+        self
+    }
+
+    /// Install packages quietly, as usually wanted in CI, by passing
+    /// `--no-audit`, `--no-fund` and `--no-progress` to `npm install`/`npm
+    /// ci`. This skips the vulnerability audit and the funding message and
+    /// hides the progress bar, see [`no_progress`].
+    ///
+    /// [`no_progress`]: struct.Build.html#method.no_progress
+    pub fn ci_quiet(&mut self) -> &mut Self {
+        self.no_audit = true;
+        self.no_fund = true;
+        self.no_progress = true;
+        self
+    }
+
+    /// Pass `--no-save` to `npm install`/`npm ci`, so installing never
+    /// modifies `package.json` or `package-lock.json`, e.g. for read-only
+    /// source checkouts.
+    ///
+    /// `npm ci` never writes these files anyway, this matters for `npm
+    /// install`, which otherwise updates the lockfile when it does not
+    /// match `package.json`. Combine with [`verify_lockfile_sync`] to fail
+    /// in that case instead.
+    ///
+    /// [`verify_lockfile_sync`]: struct.Build.html#method.verify_lockfile_sync
+    pub fn no_save(&mut self, enable: bool) -> &mut Self {
+        self.no_save = enable;
+        self
+    }
+
+    /// Pass `--foreground-scripts` to `npm install`/`npm ci`, so the output
+    /// of the install scripts of dependencies is shown instead of hidden,
+    /// e.g. to debug a native module that fails to build. Off by default,
+    /// as in npm.
+    ///
+    /// This only applies to installing packages.
+    pub fn foreground_scripts(&mut self, enable: bool) -> &mut Self {
+        self.foreground_scripts = enable;
+        self
+    }
+
+    /// If `npm ci` fails because `package-lock.json` is out of sync with
+    /// `package.json`, print a `cargo:warning` and run `npm install`
+    /// instead, which updates the lockfile. Other failures are reported as
+    /// usual. Defaults to `false`, as builds falling back are no longer
+    /// reproducible.
+    ///
+    /// npm only signals these errors in its output, so with this enabled
+    /// the output of `npm ci` is captured and passed on once it finished,
+    /// to the [`on_output_line`] closure, with the [`output_prefix`] and to
+    /// the files of [`log_to`] as usual. A failure counts as a lockfile
+    /// error if the output contains `can only install packages when your
+    /// package.json and package-lock.json`, the start of the message npm
+    /// 6 and later print for this error. The error code `EUSAGE` npm uses
+    /// along with it is not enough, as it covers every usage error.
+    ///
+    /// This only applies to npm, other package managers never fall back.
+    ///
+    /// [`on_output_line`]: struct.Build.html#method.on_output_line
+    /// [`output_prefix`]: struct.Build.html#method.output_prefix
+    /// [`log_to`]: struct.Build.html#method.log_to
+    pub fn ci_fallback_to_install(&mut self, enable: bool) -> &mut Self {
+        self.ci_fallback = enable;
+        self
+    }
+
+    /// Pass `--prefer-dedupe` to `npm install`/`npm ci`, so npm prefers
+    /// deduplicating packages over picking the newest version of each.
+    ///
+    /// Like [`run_dedupe`] this can change `package-lock.json` in the
+    /// target directory.
+    ///
+    /// [`run_dedupe`]: struct.Build.html#method.run_dedupe
+    pub fn prefer_dedupe(&mut self, enable: bool) -> &mut Self {
+        self.prefer_dedupe = enable;
+        self
+    }
+
+    /// Set the layout of `node_modules`, passed to `npm install`/`npm ci`
+    /// as `--install-strategy`.
+    ///
+    /// npm currently knows `hoisted` (the default), `nested`, `shallow` and
+    /// `linked`. The value is passed through verbatim, so it is up to npm
+    /// to reject unknown strategies. Requires npm 9 or later.
+    pub fn install_strategy(&mut self, strategy: &str) -> &mut Self {
+        self.install_strategy = Some(strategy.into());
+        self
+    }
+
+    /// Authenticate to `registry` with `token` while installing packages,
+    /// e.g. to install private packages in CI.
+    ///
+    /// `registry` is a URL like `https://npm.example.com/` or just the host
+    /// and path. The token is passed to npm through an `npm_config_`
+    /// environment variable of the install command only, so it is neither
+    /// written to an `.npmrc` nor visible to scripts, and it is redacted in
+    /// the `Debug` output of the `Build`.
+    pub fn auth_token(&mut self, registry: &str, token: &str) -> &mut Self {
+        let registry = registry
+            .split_once("://")
+            .map_or(registry, |(_, rest)| rest)
+            .trim_start_matches('/')
+            .trim_end_matches('/');
+        self.auth_tokens
+            .push((format!("//{}/", registry), Secret(token.into())));
+        self
+    }
+
+    /// Whether npm verifies TLS certificates of registries while installing
+    /// packages. Defaults to `true`.
+    ///
+    /// Disabling this sets `npm_config_strict_ssl=false` for installing,
+    /// which allows internal registries with self-signed certificates but
+    /// also exposes the install to man-in-the-middle attacks. A
+    /// `cargo:warning` is printed whenever packages are installed this way,
+    /// so it shows up in the build log.
+    pub fn strict_ssl(&mut self, enable: bool) -> &mut Self {
+        self.strict_ssl = enable;
+        self
+    }
+
+    /// Set the directory npm uses for temporary files while installing
+    /// packages, e.g. when `/tmp` is too small for large downloads. The
+    /// directory is created if it does not exist.
+    ///
+    /// npm 7 and later use the temporary directory of the operating
+    /// system, so this sets `TMPDIR` on Unix and `TEMP` and `TMP` on
+    /// Windows. Older versions read `npm_config_tmp` instead, which is set
+    /// as well. Values set with [`env`] are overridden for installing.
+    /// Running scripts is not affected.
+    ///
+    /// [`env`]: struct.Build.html#method.env
+    pub fn tmp_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.tmp_dir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Run npm and everything it starts with `dir` as the home directory,
+    /// so the user configuration and cache of the developer are not used,
+    /// for more hermetic builds. The directory is created if it does not
+    /// exist.
+    ///
+    /// On Unix this sets `HOME`. On Windows programs, npm included, look up
+    /// the home directory in `USERPROFILE` instead, which is set as well as
+    /// `HOME` for tools ported from Unix. Values set with [`env`] take
+    /// precedence.
+    ///
+    /// [`env`]: struct.Build.html#method.env
+    pub fn home_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.home_dir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Run `npm dedupe` after installing packages, to reduce the size of
+    /// the dependency tree.
+    ///
+    /// `npm dedupe` rewrites `package-lock.json` in the target directory, so
+    /// the lockfile may no longer match the one in the project directory.
+    pub fn run_dedupe(&mut self) -> &mut Self {
+        self.dedupe = true;
+        self
+    }
+
+    /// Run `command` with `args` after npm installed packages and before
+    /// any script, e.g. `bower install` for legacy projects. Commands
+    /// registered by multiple calls run in order.
+    ///
+    /// The commands run in the directory npm is run in, with the configured
+    /// environment variables, and are skipped whenever installing with npm
+    /// is. If a command fails, installing fails with
+    /// [`BuildError::AdditionalInstallFailed`].
+    ///
+    /// [`BuildError::AdditionalInstallFailed`]: enum.BuildError.html#variant.AdditionalInstallFailed
+    pub fn additional_install(&mut self, command: &str, args: &[&str]) -> &mut Self {
+        self.additional_installs.push((
+            command.into(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// Fail the build if `node_modules` in the [`target_directory`] is
+    /// larger than `bytes` after installing packages, returning
+    /// [`BuildError::NodeModulesTooLarge`].
+    ///
+    /// This guards against dependency bloat. Walking `node_modules` can take
+    /// a while for large projects, which is why it is off by default.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`BuildError::NodeModulesTooLarge`]: enum.BuildError.html#variant.NodeModulesTooLarge
+    pub fn max_node_modules_size(&mut self, bytes: u64) -> &mut Self {
+        self.max_node_modules_size = Some(bytes);
+        self
+    }
+
+    /// Fail with [`BuildError::InsufficientDiskSpace`] before installing
+    /// packages if less than `bytes` are free on the filesystem of the
+    /// [`target_directory`], instead of leaving a broken `node_modules`
+    /// behind when the disk fills up midway.
+    ///
+    /// The check is skipped when nothing is installed.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`BuildError::InsufficientDiskSpace`]: enum.BuildError.html#variant.InsufficientDiskSpace
+    pub fn require_free_space(&mut self, bytes: u64) -> &mut Self {
+        self.required_free_space = Some(bytes);
+        self
+    }
+
+    /// Hold an exclusive lock while copying files and installing packages,
+    /// so concurrent builds using the same [`target_directory`], e.g.
+    /// `cargo build` and `cargo test`, prepare it one after the other
+    /// instead of corrupting `node_modules`. Defaults to `false`.
+    ///
+    /// The lock file `.<name>.npm-rs.lock` is created next to the target
+    /// directory `<name>`, outside of it, so syncing or removing copied
+    /// items never deletes it. The lock is released when installing
+    /// finishes or fails, and by the operating system if the build script
+    /// dies. The file itself is left in place. Running scripts is not
+    /// serialized.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    pub fn serialize_installs(&mut self, enable: bool) -> &mut Self {
+        self.serialize_installs = enable;
+        self
+    }
+
+    /// Fail the build if any dependency is outdated, returning
+    /// [`BuildError::OutdatedDependencies`] with the names reported by
+    /// `npm outdated`.
+    ///
+    /// The check runs once, after installing packages. New releases of any
+    /// dependency make it fail without a change to the project, so this is
+    /// best suited for scheduled CI jobs rather than every local build.
+    ///
+    /// [`BuildError::OutdatedDependencies`]: enum.BuildError.html#variant.OutdatedDependencies
+    pub fn fail_if_outdated(&mut self) -> &mut Self {
+        self.fail_if_outdated = true;
+        self
+    }
+
+    /// Check `node_modules` with `npm ls --depth=0` after installing
+    /// packages and before running anything, failing with
+    /// [`BuildError::BrokenInstall`] if npm reports missing or invalid
+    /// packages, e.g. after an interrupted install.
+    ///
+    /// The check runs once, like [`fail_if_outdated`], also if installing
+    /// was skipped. It costs an extra npm process, which is why it is off
+    /// by default.
+    ///
+    /// [`fail_if_outdated`]: struct.Build.html#method.fail_if_outdated
+    /// [`BuildError::BrokenInstall`]: enum.BuildError.html#variant.BrokenInstall
+    pub fn verify_install(&mut self) -> &mut Self {
+        self.verify_install = true;
+        self
+    }
+
+    /// After installing packages, list the packages locked in
+    /// `package-lock.json` as `name@version`, e.g. for license or supply
+    /// chain audits.
+    ///
+    /// With `to` set to `None` every package is emitted as a
+    /// `cargo:warning` line, otherwise the list is written to the file at
+    /// `to`, one package per line. Lockfiles of every npm version are
+    /// supported, those of other package managers are not.
+    ///
+    /// # Errors
+    ///
+    /// Installing fails with [`BuildError::CannotReadManifest`] or
+    /// [`BuildError::InvalidManifest`] if the lockfile cannot be read, and
+    /// with [`BuildError::CannotWriteManifest`] if the list cannot be
+    /// written.
+    ///
+    /// [`BuildError::CannotReadManifest`]: enum.BuildError.html#variant.CannotReadManifest
+    /// [`BuildError::InvalidManifest`]: enum.BuildError.html#variant.InvalidManifest
+    /// [`BuildError::CannotWriteManifest`]: enum.BuildError.html#variant.CannotWriteManifest
+    pub fn emit_dependency_list(&mut self, to: Option<PathBuf>) -> &mut Self {
+        self.emit_dependencies = true;
+        self.dependency_list = to;
+        self
+    }
+
+    /// Limit the resources of every npm process and the processes it
+    /// starts, such as a bundler, so a runaway build fails instead of
+    /// exhausting the machine.
+    ///
+    /// The limits are set with `setrlimit` before npm starts and are
+    /// inherited by its child processes. Only supported on Unix, elsewhere
+    /// they are ignored. They are also ignored when running npm in a
+    /// pseudo terminal with [`use_pty`].
+    ///
+    /// ```no_run
+    /// use npm_rs::{Build, ResourceLimits};
+    ///
+    /// Build::new()
+    ///     .resource_limits(ResourceLimits {
+    ///         max_address_space: Some(4 << 30),
+    ///         ..ResourceLimits::default()
+    ///     })
+    ///     .run_script("build");
+    /// ```
+    ///
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn resource_limits(&mut self, limits: ResourceLimits) -> &mut Self {
+        self.resource_limits = Some(limits);
+        self
+    }
+
+    /// Set the umask of npm and the processes it starts, so files they
+    /// create get the same permissions on every machine, e.g. `0o022`.
+    ///
+    /// Only supported on Unix, elsewhere this does nothing. Items copied to
+    /// the target directory by this crate are not created by npm and keep
+    /// the permissions of their source. Like [`resource_limits`] this is
+    /// ignored when running npm in a pseudo terminal with [`use_pty`].
+    ///
+    /// [`resource_limits`]: struct.Build.html#method.resource_limits
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn umask(&mut self, mask: u32) -> &mut Self {
+        self.umask = Some(mask);
+        self
+    }
+
+    /// Terminate npm and everything it started when the build script is
+    /// interrupted, instead of leaving orphaned processes behind that keep
+    /// writing to `node_modules`.
+    ///
+    /// On Unix every npm process is started in its own process group, and
+    /// a handler for SIGINT and SIGTERM sends SIGTERM to these groups before
+    /// the build script exits as it would without the handler. As npm no
+    /// longer is in the foreground process group, it does not receive
+    /// Ctrl-C from the terminal directly. This replaces any handler for
+    /// these signals installed by other code in the build script and is
+    /// ignored when running npm in a pseudo terminal with [`use_pty`].
+    ///
+    /// On Windows this does nothing, as Ctrl-C already reaches every
+    /// process attached to the console, npm included.
+    ///
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn handle_interrupts(&mut self) -> &mut Self {
+        self.handle_interrupts = true;
+        self
+    }
+
+    /// Call `f` with each line npm prints to stdout or stderr while it
+    /// runs, e.g. to collect warnings as they happen. The line is passed
+    /// without its line ending.
+    ///
+    /// The output is still forwarded to the output of the build script, or
+    /// to the files of [`log_to`], unless disabled with
+    /// [`forward_output_lines`]. Each stream is read on its own thread, so
+    /// `f` is called from these reader threads and must be `Send`. Calls
+    /// never overlap, but lines of stdout and stderr may arrive in a
+    /// different order than npm printed them. Commands run by
+    /// [`run_scripts_parallel`] or in a pseudo terminal with [`use_pty`]
+    /// are not streamed.
+    ///
+    /// [`log_to`]: struct.Build.html#method.log_to
+    /// [`forward_output_lines`]: struct.Build.html#method.forward_output_lines
+    /// [`run_scripts_parallel`]: struct.Build.html#method.run_scripts_parallel
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn on_output_line<F: FnMut(&str) + Send + 'static>(&mut self, f: F) -> &mut Self {
+        self.line_hook = Some(Arc::new(Mutex::new(LineHook(Box::new(f)))));
+        self
+    }
+
+    /// Whether output passed to [`on_output_line`] is also forwarded to the
+    /// output of the build script or the log files. Defaults to `true`.
+    ///
+    /// [`on_output_line`]: struct.Build.html#method.on_output_line
+    pub fn forward_output_lines(&mut self, enable: bool) -> &mut Self {
+        self.forward_output_lines = enable;
+        self
+    }
+
+    /// Put `prefix` and a space in front of every line npm prints, e.g.
+    /// `[frontend] Building...`, to tell apart the output of several
+    /// `Build`s in one build script. This applies to installing packages
+    /// and running scripts, and to the [`log_to`] files.
+    ///
+    /// Like [`on_output_line`] this reads the output line by line, so
+    /// progress bars redrawing a line show up as separate lines. The
+    /// output of scripts run in a pseudo terminal with [`use_pty`] is not
+    /// prefixed.
+    ///
+    /// [`log_to`]: struct.Build.html#method.log_to
+    /// [`on_output_line`]: struct.Build.html#method.on_output_line
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn output_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.output_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Run npm attached to a pseudo terminal instead of plain pipes, for
+    /// tools that only print colors or progress when they detect a TTY.
+    ///
+    /// The output of npm is forwarded to the output of the build script, or
+    /// to the stdout file of [`log_to`]. A terminal has a single output
+    /// stream, so stderr is merged into stdout. Stdio settings made with
+    /// [`configure_command`] are ignored, and [`run_scripts_parallel`]
+    /// always uses pipes.
+    ///
+    /// Supported on Unix systems and on Windows 10 version 1809 or later
+    /// through ConPTY. Requires the `pty` feature.
+    ///
+    /// [`log_to`]: struct.Build.html#method.log_to
+    /// [`configure_command`]: struct.Build.html#method.configure_command
+    /// [`run_scripts_parallel`]: struct.Build.html#method.run_scripts_parallel
+    #[cfg(feature = "pty")]
+    pub fn use_pty(&mut self, enable: bool) -> &mut Self {
+        self.use_pty = enable;
+        self
+    }
+
+    /// Set the target directory. Defaults to the current directory, which
+    /// for a build script is the directory the `build.rs` resides in.
+    /// This is the directory the npm project gets copied to.
+    ///
+    /// If you set this to something other than the [`project_directory`],
+    /// you must call either [`copy_items`] or [`copy_all`] to tell the
+    /// `Build` how to move the project files.
+    ///
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`copy_items`]: struct.Build.html#method.copy_items
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    pub fn target_directory<P: AsRef<Path>>(&mut self, directory: P) -> &mut Self {
+        self.target_directory = directory.as_ref().into();
+        self.installed = false;
+        self.files_prepared = false;
+        self
+    }
+
+    /// Sets the project directory, which is where the source of the npm
+    /// project is located. Defaults to the current directory, which for
+    /// a build script is the directory the `build.rs` resides in.
+    pub fn project_directory<P: AsRef<Path>>(&mut self, directory: P) -> &mut Self {
+        self.project_directory = directory.as_ref().into();
+        self.installed = false;
+        self.files_prepared = false;
+        self
+    }
+
+    /// Tells the `Build` to copy the selected items from [`project_directory`]
+    /// to [`target_directory`]
+    ///
+    /// Has no effect if [`project_directory`] and [`target_directory`] are
+    /// the same.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn copy_items<L: IntoIterator<Item = P>, P: AsRef<Path>>(&mut self, items: L) -> &mut Self {
+        self.copy = CopyItems::Some(items.into_iter().map(|p| p.as_ref().into()).collect());
+        self.copy_once = false;
+        self.changed_since = None;
+        self
+    }
+
+    /// Expand `${VAR}` references in the paths passed to [`copy_items`] or
+    /// [`sync_items`] with the values of environment variables, e.g.
+    /// `config/${TARGET}`.
+    ///
+    /// Referencing a variable that is not set is an error rather than
+    /// expanding to an empty string.
+    ///
+    /// [`copy_items`]: struct.Build.html#method.copy_items
+    /// [`sync_items`]: struct.Build.html#method.sync_items
+    pub fn expand_env_in_paths(&mut self) -> &mut Self {
+        self.expand_env_in_paths = true;
+        self
+    }
+
+    /// Tells the `Build` to copy all files from [`project_directory`]
+    /// to [`target_directory`]
+    ///
+    /// This does not copy the `node_modules` directory.
+    ///
+    /// Has no effect if [`project_directory`] and [`target_directory`] are
+    /// the same.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn copy_all(&mut self) -> &mut Self {
+        self.copy = CopyItems::All;
+        self.copy_once = false;
+        self.changed_since = None;
+        self
+    }
+
+    /// Like [`copy_all`], but only copy if the [`target_directory`] is
+    /// empty apart from `node_modules`, e.g. on the first build. Later
+    /// builds leave the target directory alone, assuming it is managed
+    /// elsewhere.
+    ///
+    /// Changes to the [`project_directory`] do not reach the target
+    /// directory once it was filled. Remove its contents to copy again.
+    ///
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn copy_if_target_empty(&mut self) -> &mut Self {
+        self.copy = CopyItems::All;
+        self.copy_once = true;
+        self.changed_since = None;
+        self
+    }
+
+    /// Like [`copy_all`], but once the [`target_directory`] was filled only
+    /// copy the files that differ from `git_ref`, as listed by `git diff
+    /// --name-only <git_ref>` in the [`project_directory`], e.g. to speed
+    /// up CI builds of large repositories. Changed files keep their place
+    /// below the project directory, files deleted since `git_ref` are
+    /// removed from the target directory.
+    ///
+    /// Files changed in the working tree count as changed, untracked files
+    /// do not. Everything is copied if the target directory is empty apart
+    /// from `node_modules`, or if git fails, e.g. because the project is
+    /// not in a repository.
+    ///
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn copy_changed_since(&mut self, git_ref: &str) -> &mut Self {
+        self.copy = CopyItems::All;
+        self.copy_once = false;
+        self.changed_since = Some(git_ref.into());
+        self
+    }
+
+    /// With [`copy_all`], skip the top-level entries of the
+    /// [`project_directory`] whose name starts with a `.`, such as `.git`,
+    /// `.env` or `.DS_Store`. Defaults to `false`.
+    ///
+    /// Hidden files inside copied directories are still copied.
+    ///
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn exclude_hidden(&mut self, enable: bool) -> &mut Self {
+        self.exclude_hidden = enable;
+        self
+    }
+
+    /// With [`copy_all`], copy the entries of the [`project_directory`]
+    /// sorted by path instead of in the order the filesystem lists them.
+    /// Defaults to `true`.
+    ///
+    /// This keeps the copy order and the emitted `cargo:rerun-if-changed`
+    /// lines stable between builds and machines.
+    ///
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn deterministic_order(&mut self, enable: bool) -> &mut Self {
+        self.deterministic_order = enable;
+        self
+    }
+
+    /// Like [`copy_items`], but makes the [`target_directory`] mirror the
+    /// selected items: everything else in it, except `node_modules`, is
+    /// removed before copying.
+    ///
+    /// This gets rid of stale files left over from previous builds without
+    /// having to reinstall node packages.
+    ///
+    /// Has no effect if [`project_directory`] and [`target_directory`] are
+    /// the same.
+    ///
+    /// [`copy_items`]: struct.Build.html#method.copy_items
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn sync_items<L: IntoIterator<Item = P>, P: AsRef<Path>>(&mut self, items: L) -> &mut Self {
+        self.copy = CopyItems::Sync(items.into_iter().map(|p| p.as_ref().into()).collect());
+        self.copy_once = false;
+        self.changed_since = None;
+        self
+    }
+
+    /// Remove the [`target_directory`] when this `Build` is dropped.
+    ///
+    /// This is meant for scratch builds whose results are not needed after
+    /// the build script finishes. To avoid deleting anything important the
+    /// directory is only removed if
+    /// * it lies strictly inside `OUT_DIR`, so this does nothing outside of
+    ///   a build script,
+    /// * it contains no `..` components,
+    /// * it is neither the [`project_directory`] nor one of its parents.
+    ///
+    /// Errors while removing the directory are ignored.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn cleanup_on_drop(&mut self) -> &mut Self {
+        self.cleanup_on_drop = true;
+        self
+    }
+
+    /// Copy the top-level items to the [`target_directory`] on one thread
+    /// each, which speeds up copying large source trees. Defaults to
+    /// `false`.
+    ///
+    /// Every item is still replaced as a whole. If copying items fails, the
+    /// remaining items are copied anyway and the errors of all failed items
+    /// are returned together in a [`BuildError::CopyFailed`].
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`BuildError::CopyFailed`]: enum.BuildError.html#variant.CopyFailed
+    pub fn parallel_copy(&mut self, enable: bool) -> &mut Self {
+        self.parallel_copy = enable;
+        self
+    }
+
+    /// After copying, check that every file below the copied items exists
+    /// in the [`target_directory`], failing with
+    /// [`BuildError::CopyIncomplete`] listing the missing ones otherwise.
+    ///
+    /// With [`copy_all`], `node_modules` is skipped, as it is not copied.
+    /// Only the presence of files is checked, not their contents.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`BuildError::CopyIncomplete`]: enum.BuildError.html#variant.CopyIncomplete
+    pub fn verify_copy(&mut self) -> &mut Self {
+        self.verify_copy = true;
+        self
+    }
+
+    /// Make read-only files in the [`target_directory`] writable before
+    /// replacing them with copied items. Defaults to `false`.
+    ///
+    /// Copying over read-only files fails, especially on Windows where some
+    /// checkouts mark files read-only. On Unix the owner gets write
+    /// permission, on Windows the read-only attribute is cleared.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    pub fn overwrite_readonly(&mut self, enable: bool) -> &mut Self {
+        self.overwrite_readonly = enable;
+        self
+    }
+
+    /// Remove the items copied to the [`target_directory`] after each
+    /// successful [`run_script`], keeping only the build outputs listed in
+    /// `keep` and `node_modules`, to keep `OUT_DIR` small.
+    ///
+    /// Paths in `keep` are relative to the target directory. Items are
+    /// removed as a whole, so a copied directory containing a path in
+    /// `keep` is kept entirely. Files created by npm are never removed.
+    /// Nothing is removed if the target is the [`project_directory`]. The
+    /// next script copies the items again but keeps the installed
+    /// packages.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn remove_source_after(&mut self, keep: &[&Path]) -> &mut Self {
+        self.remove_source_after = Some(keep.iter().map(|path| path.to_path_buf()).collect());
+        self
+    }
+
+    /// Install packages and run scripts in a fresh temporary directory
+    /// instead of the [`target_directory`], and copy only `outputs` to the
+    /// target directory after each successful script.
+    ///
+    /// This isolates the build from stale files of earlier builds and keeps
+    /// the target directory free of sources and `node_modules`. The items to
+    /// copy to the temporary directory are selected as usual, e.g. with
+    /// [`copy_all`]. Paths in `outputs` are relative, their place in the
+    /// target directory matches the one in the temporary directory. The
+    /// temporary directory is removed when the `Build` is dropped.
+    ///
+    /// A script that succeeds without producing all outputs fails with
+    /// [`BuildError::MissingOutput`].
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`BuildError::MissingOutput`]: enum.BuildError.html#variant.MissingOutput
+    pub fn use_temp_target<L: IntoIterator<Item = P>, P: AsRef<Path>>(
+        &mut self,
+        outputs: L,
+    ) -> &mut Self {
+        self.temp_outputs = Some(outputs.into_iter().map(|p| p.as_ref().into()).collect());
+        self
+    }
+
+    /// Read environment variables for the Rust compilation from `file`
+    /// after each successful script, and emit a `cargo:rustc-env` line for
+    /// each, so the crate can use values such as an asset hash produced by
+    /// the frontend build with `env!`.
+    ///
+    /// `file` is relative to the directory npm is run in and must contain a
+    /// JSON object like `{"ASSET_HASH": "d41d8cd9"}`, with strings, numbers
+    /// or booleans as values. Nothing is emitted if a script does not write
+    /// the file.
+    ///
+    /// # Errors
+    ///
+    /// Running a script fails with [`BuildError::InvalidManifest`] if the
+    /// file is not valid JSON, or with [`BuildError::InvalidEnvOutput`] if
+    /// it contains anything else.
+    ///
+    /// [`BuildError::InvalidManifest`]: enum.BuildError.html#variant.InvalidManifest
+    /// [`BuildError::InvalidEnvOutput`]: enum.BuildError.html#variant.InvalidEnvOutput
+    pub fn emit_env_from<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.env_output = Some(file.as_ref().into());
+        self
+    }
+
+    /// Prints the variables of the [`emit_env_from`] file as Cargo
+    /// instructions.
+    ///
+    /// [`emit_env_from`]: struct.Build.html#method.emit_env_from
+    fn emit_env(&self) -> Result<(), BuildError> {
+        let path = match &self.env_output {
+            Some(file) => self.effective_target().join(file),
+            None => return Ok(()),
+        };
+        if !path.is_file() {
+            return Ok(());
+        }
+        let vars = match read_manifest(&path)? {
+            serde_json::Value::Object(vars) => vars,
+            _ => return Err(BuildError::InvalidEnvOutput(path)),
+        };
+        let mut lines = Vec::new();
+        for (key, value) in vars {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Number(value) => value.to_string(),
+                serde_json::Value::Bool(value) => value.to_string(),
+                _ => return Err(BuildError::InvalidEnvOutput(path)),
+            };
+            if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+                return Err(BuildError::InvalidEnvOutput(path));
+            }
+            lines.push(format!("cargo:rustc-env={}={}", key, value));
+        }
+        for line in lines {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+
+    /// Fail with [`BuildError::OutputHashMismatch`] unless the outputs of
+    /// each successful script hash to `expected`, a hex encoded SHA-256
+    /// digest, to pin exact build outputs and detect nondeterminism.
+    ///
+    /// The outputs are the files declared with [`use_temp_target`] and
+    /// [`copy_outputs_to`], and those matching [`expect_outputs_glob`],
+    /// including everything below declared directories. Without any
+    /// declared outputs the check fails with [`BuildError::NoOutputsToHash`]
+    /// rather than hashing sources. The digest covers the relative path,
+    /// length and contents of every file, in order of their paths, so it is
+    /// the same on every machine. The error contains the actual digest,
+    /// which makes it easy to pin a new one.
+    ///
+    /// [`BuildError::OutputHashMismatch`]: enum.BuildError.html#variant.OutputHashMismatch
+    /// [`BuildError::NoOutputsToHash`]: enum.BuildError.html#variant.NoOutputsToHash
+    /// [`use_temp_target`]: struct.Build.html#method.use_temp_target
+    /// [`copy_outputs_to`]: struct.Build.html#method.copy_outputs_to
+    /// [`expect_outputs_glob`]: struct.Build.html#method.expect_outputs_glob
+    pub fn assert_output_hash(&mut self, expected: &str) -> &mut Self {
+        self.output_hash = Some(expected.into());
+        self
+    }
+
+    /// Fail with [`BuildError::NoOutputMatched`] naming the pattern if,
+    /// after a successful script, no file in the directory npm is run in
+    /// matches one of `patterns`, e.g. `dist/main.*.js` for bundles with
+    /// content hashes in their names.
+    ///
+    /// Patterns are relative to the directory npm is run in and use the
+    /// syntax of the [`glob`] crate, where `**` matches any number of
+    /// directories. Invalid patterns fail with
+    /// [`BuildError::InvalidOutputGlob`].
+    ///
+    /// [`BuildError::NoOutputMatched`]: enum.BuildError.html#variant.NoOutputMatched
+    /// [`glob`]: https://docs.rs/glob
+    /// [`BuildError::InvalidOutputGlob`]: enum.BuildError.html#variant.InvalidOutputGlob
+    pub fn expect_outputs_glob(&mut self, patterns: &[&str]) -> &mut Self {
+        self.output_globs
+            .extend(patterns.iter().map(|pattern| pattern.to_string()));
+        self
+    }
+
+    /// Checks that every pattern of [`expect_outputs_glob`] matches a file.
+    ///
+    /// [`expect_outputs_glob`]: struct.Build.html#method.expect_outputs_glob
+    fn check_output_globs(&self) -> Result<(), BuildError> {
+        let target = glob::Pattern::escape(&self.effective_target().to_string_lossy());
+        for pattern in &self.output_globs {
+            let full = format!("{}/{}", target, pattern);
+            let mut matches =
+                glob::glob(&full).map_err(|source| BuildError::InvalidOutputGlob {
+                    pattern: pattern.clone(),
+                    source,
+                })?;
+            if !matches.any(|path| path.is_ok_and(|path| path.is_file())) {
+                return Err(BuildError::NoOutputMatched(pattern.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the outputs against [`assert_output_hash`].
+    ///
+    /// [`assert_output_hash`]: struct.Build.html#method.assert_output_hash
+    fn check_output_hash(&self) -> Result<(), BuildError> {
+        let expected = match &self.output_hash {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let outputs = self.declared_outputs();
+        if outputs.is_empty() {
+            return Err(BuildError::NoOutputsToHash);
+        }
+        let actual = hash::output_hash(self.effective_target(), &outputs)?;
+        if actual.eq_ignore_ascii_case(expected) {
+            Ok(())
+        } else {
+            Err(BuildError::OutputHashMismatch {
+                expected: expected.clone(),
+                actual,
+            })
+        }
+    }
+
+    /// Processes the outputs of a successful script.
+    fn finish_script(&self) -> Result<(), BuildError> {
+        self.check_output_globs()?;
+        self.emit_env()?;
+        self.copy_outputs()?;
+        self.check_output_hash()?;
+        self.write_build_manifest()
+    }
+
+    /// Write a JSON summary of the build to `path` after every successful
+    /// script, e.g. to a file in `OUT_DIR` for auditing or reproducibility
+    /// tooling. Existing files are overwritten.
+    ///
+    /// The summary is an object with these keys:
+    ///
+    /// * `schema_version`: `1`, increased on incompatible changes
+    /// * `package` and `package_manager`, as in the [`report`]
+    /// * `node_env`: the configured `NODE_ENV`
+    /// * `scripts`: the names of the scripts run so far, in order
+    /// * `inputs`: the items copied to the [`target_directory`]
+    /// * `outputs`: the files declared with [`use_temp_target`] and
+    ///   [`copy_outputs_to`], and those matching [`expect_outputs_glob`],
+    ///   relative to the directory npm is run in
+    /// * `commands`: every command run, with its `args`, `node_env`,
+    ///   `duration_secs` and `exit_code`
+    ///
+    /// # Errors
+    ///
+    /// Running a script fails with [`BuildError::CannotWriteManifest`] if
+    /// the file cannot be written.
+    ///
+    /// [`report`]: struct.Build.html#method.report
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`use_temp_target`]: struct.Build.html#method.use_temp_target
+    /// [`copy_outputs_to`]: struct.Build.html#method.copy_outputs_to
+    /// [`expect_outputs_glob`]: struct.Build.html#method.expect_outputs_glob
+    /// [`BuildError::CannotWriteManifest`]: enum.BuildError.html#variant.CannotWriteManifest
+    pub fn write_manifest<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.build_manifest = Some(path.as_ref().into());
+        self
+    }
+
+    fn write_build_manifest(&self) -> Result<(), BuildError> {
+        let path = match &self.build_manifest {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let report = self.report();
+        let display = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let scripts: Vec<_> = report
+            .commands
+            .iter()
+            .filter_map(|command| self.script_of(&command.args))
+            .collect();
+        let commands: Vec<_> = report
+            .commands
+            .iter()
+            .map(|command| {
+                serde_json::json!({
+                    "args": command.args,
+                    "node_env": command.node_env,
+                    "duration_secs": command.duration.as_secs_f64(),
+                    "exit_code": command.exit_code,
+                })
+            })
+            .collect();
+        let manifest = serde_json::json!({
+            "schema_version": 1,
+            "package": report.package,
+            "package_manager": report.package_manager,
+            "node_env": self.node_env.to_env_var(),
+            "scripts": scripts,
+            "inputs": display(&self.copied),
+            "outputs": display(&self.declared_outputs()),
+            "commands": commands,
+        });
+        write_manifest(path, &manifest)
+    }
+
+    /// The outputs known to this `Build`, relative to the directory npm is
+    /// run in.
+    fn declared_outputs(&self) -> Vec<PathBuf> {
+        let mut outputs: Vec<PathBuf> = self.temp_outputs.iter().flatten().cloned().collect();
+        for (exported, _) in &self.exports {
+            outputs.extend(exported.iter().cloned());
+        }
+        let target = self.effective_target();
+        let escaped = glob::Pattern::escape(&target.to_string_lossy());
+        for pattern in &self.output_globs {
+            if let Ok(matches) = glob::glob(&format!("{}/{}", escaped, pattern)) {
+                outputs.extend(
+                    matches
+                        .flatten()
+                        .filter(|path| path.is_file())
+                        .filter_map(|path| path.strip_prefix(target).ok().map(PathBuf::from)),
+                );
+            }
+        }
+        outputs.sort();
+        outputs.dedup();
+        outputs
+    }
+
+    /// Whether items need to be copied from the project directory.
+    fn copies_items(&self) -> bool {
+        self.temp_outputs.is_some() || self.project_directory != self.target_directory
+    }
+
+    /// Copy `outputs` from the directory npm is run in to `dest` after
+    /// each successful script, e.g. to commit generated assets to the
+    /// repository. Can be called multiple times for several destinations.
+    ///
+    /// Paths in `outputs` are relative, their place in `dest` matches the
+    /// one in the target directory. `dest` is created if it does not exist
+    /// and existing files are overwritten. A script that succeeds without
+    /// producing all outputs fails with [`BuildError::MissingOutput`].
+    ///
+    /// [`BuildError::MissingOutput`]: enum.BuildError.html#variant.MissingOutput
+    pub fn copy_outputs_to<P: AsRef<Path>>(&mut self, outputs: &[&Path], dest: P) -> &mut Self {
+        self.exports.push((
+            outputs.iter().map(|output| output.to_path_buf()).collect(),
+            dest.as_ref().into(),
+        ));
+        self
+    }
+
+    /// Copies the outputs of [`use_temp_target`] to the target directory,
+    /// and those of [`copy_outputs_to`] to their destination.
+    ///
+    /// [`use_temp_target`]: struct.Build.html#method.use_temp_target
+    /// [`copy_outputs_to`]: struct.Build.html#method.copy_outputs_to
+    fn copy_outputs(&self) -> Result<(), BuildError> {
+        if let (Some(outputs), Some(temp)) = (&self.temp_outputs, &self.temp_target) {
+            copy_outputs(outputs, temp.path(), or_current_dir(&self.target_directory))?;
+        }
+        for (outputs, dest) in &self.exports {
+            copy_outputs(outputs, self.effective_target(), dest)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the copied items not listed in `remove_source_after`.
+    fn remove_source(&mut self) -> Result<(), BuildError> {
+        let keep = match &self.remove_source_after {
+            Some(keep) if !self.copied.is_empty() => keep,
+            _ => return Ok(()),
+        };
+        let target = self.effective_target();
+        let stale: Vec<_> = self
+            .copied
+            .iter()
+            .filter(|item| *item != Path::new("node_modules"))
+            .filter(|item| !keep.iter().any(|path| path.starts_with(item)))
+            .map(|item| target.join(item))
+            .collect();
+        remove_items(&stale)?;
+        self.copied.clear();
+        self.files_prepared = false;
+        Ok(())
+    }
+
+    fn target_is_removable(&self) -> bool {
+        let out_dir = match env::var_os("OUT_DIR") {
+            Some(dir) => absolute_path(Path::new(&dir)),
+            None => return false,
+        };
+        let target = absolute_path(&self.target_directory);
+        let project = absolute_path(&self.project_directory);
+        let escapes = target.components().any(|c| c == Component::ParentDir);
+        !escapes
+            && target != out_dir
+            && target.starts_with(&out_dir)
+            && !project.starts_with(&target)
+    }
+
+    /// Register a closure that can modify every npm [`Command`] before it
+    /// is run, both for installing packages and for running scripts.
+    ///
+    /// This is an escape hatch for arguments, environment variables or
+    /// stdio settings this crate does not support directly. The closure is
+    /// called after all of the `Build`'s own configuration was applied, so
+    /// it can override it. Multiple closures are called in the order they
+    /// were registered.
+    ///
+    /// [`Command`]: https://doc.rust-lang.org/std/process/struct.Command.html
+    pub fn configure_command<F: Fn(&mut Command) + 'static>(&mut self, f: F) -> &mut Self {
+        self.command_hooks.push(CommandHook(Box::new(f)));
+        self
+    }
+
+    /// Modify `package.json` before node packages are installed, e.g. to
+    /// set the version from Cargo or point a dependency at a local path.
+    ///
+    /// The closure is called once, after files were copied to the
+    /// [`target_directory`], with the parsed manifest. The modified manifest
+    /// is written back to the `package.json` in the target directory, and
+    /// written again whenever files are copied again later. If
+    /// the target directory is the [`project_directory`] this modifies the
+    /// project's own `package.json`. Nothing is restored if the build fails
+    /// afterwards.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    pub fn patch_manifest<F: FnOnce(&mut serde_json::Value) + 'static>(
+        &mut self,
+        f: F,
+    ) -> &mut Self {
+        self.manifest_patches.push(ManifestPatch(Box::new(f)));
+        self
+    }
+
+    /// Write `contents` to `path` in the directory npm is run in, after
+    /// files were copied and before anything is installed or run, e.g. for
+    /// a constants file generated by the build script that the frontend
+    /// build reads.
+    ///
+    /// `path` is relative to the [`target_directory`] and missing parent
+    /// directories are created. Files are written in the order this is
+    /// called, after the [`patch_manifest`] closures, replacing existing
+    /// files. If the target directory is the [`project_directory`] this
+    /// writes into the project.
+    ///
+    /// # Errors
+    ///
+    /// Running fails with [`BuildError::InjectedFileOutsideTarget`] before
+    /// any file is written if a `path` is absolute or contains `..`.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`patch_manifest`]: struct.Build.html#method.patch_manifest
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`BuildError::InjectedFileOutsideTarget`]: enum.BuildError.html#variant.InjectedFileOutsideTarget
+    pub fn inject_file<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &mut self,
+        path: P,
+        contents: C,
+    ) -> &mut Self {
+        self.injected_files
+            .push((path.as_ref().into(), contents.as_ref().to_vec()));
+        self
+    }
+
+    /// Use the npm executable at `path` instead of looking it up in `PATH`.
+    pub fn npm_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.npm = Some(path.as_ref().into());
+        self.manager = None;
+        self
+    }
+
+    /// Use the package manager pinned with corepack's `packageManager`
+    /// field in the project's `package.json`, e.g. `pnpm@8.6.0`, instead of
+    /// npm, unless [`npm_path`] is set.
+    ///
+    /// yarn and pnpm are looked up in `PATH`, where corepack's shims make
+    /// sure the pinned version is used. Where npm would run `npm ci`, they
+    /// run `install --frozen-lockfile`, or `install --immutable` for yarn 2
+    /// and later. Other options are passed unchanged, so npm specific ones
+    /// like [`max_sockets`] may not be understood. npm is used if the field
+    /// is missing or does not name npm, yarn or pnpm with a version.
+    ///
+    /// Running fails with [`BuildError::PackageManagerNotFound`] if the
+    /// pinned package manager is not installed.
+    ///
+    /// [`npm_path`]: struct.Build.html#method.npm_path
+    /// [`max_sockets`]: struct.Build.html#method.max_sockets
+    /// [`BuildError::PackageManagerNotFound`]: enum.BuildError.html#variant.PackageManagerNotFound
+    pub fn auto_detect_manager(&mut self) -> &mut Self {
+        self.detect_manager = true;
+        self
+    }
+
+    /// The `packageManager` of the project, if it names a supported package
+    /// manager and version. A corepack hash like `+sha256.…` is dropped.
+    fn detected_manager(&self) -> Option<String> {
+        let pinned = package_manager(&self.manifest_path())?;
+        let pinned = pinned.split('+').next().unwrap_or_default();
+        match pinned.split_once('@') {
+            Some(("npm" | "yarn" | "pnpm", version)) if !version.is_empty() => {
+                Some(pinned.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// The name of the package manager in use, `npm` unless another one
+    /// was detected.
+    fn manager_name(&self) -> &str {
+        self.manager
+            .as_deref()
+            .and_then(|manager| manager.split('@').next())
+            .unwrap_or("npm")
+    }
+
+    /// Set the directories searched for npm if it is not in `PATH`, in
+    /// order.
+    ///
+    /// By default common install locations are searched: the versions
+    /// installed by nvm, newest first, Volta, `/usr/local/bin` and
+    /// `/opt/homebrew/bin` on Unix, and the nvm-windows symlink, `nodejs`
+    /// in Program Files and `npm` in `APPDATA` on Windows. Pass an empty
+    /// list to only look in `PATH`.
+    pub fn search_paths(&mut self, dirs: &[PathBuf]) -> &mut Self {
+        self.npm_search_paths = Some(dirs.to_vec());
+        self
+    }
+
+    /// Looks up npm in `PATH` and the search paths.
+    fn find_npm(&self) -> Result<PathBuf, BuildError> {
+        if let Some(npm) = (self.lookup)("npm") {
+            return Ok(npm);
+        }
+        let searched = match &self.npm_search_paths {
+            Some(dirs) => dirs.clone(),
+            None => default_npm_search_paths(),
+        };
+        let executable = if cfg!(windows) { "npm.cmd" } else { "npm" };
+        searched
+            .iter()
+            .map(|dir| dir.join(executable))
+            .find(|npm| npm.is_file())
+            .ok_or_else(|| BuildError::NpmNotFound {
+                path: env::var_os("PATH"),
+                searched,
+            })
+    }
+
+    /// Set the subcommand used to run scripts, `run` by default. With `None`
+    /// the script name is passed directly, as in `pnpm build` or
+    /// `yarn build`.
+    ///
+    /// This is mostly useful together with [`npm_path`] pointing at a
+    /// different package manager or a wrapper around npm.
+    ///
+    /// [`npm_path`]: struct.Build.html#method.npm_path
+    pub fn run_verb(&mut self, verb: Option<&str>) -> &mut Self {
+        self.run_verb = verb.map(String::from);
+        self
+    }
+
+    /// Pass `flags` to the package manager when running scripts, before
+    /// the [`run_verb`], as in `pnpm --reporter=silent run build`. This is
+    /// an escape hatch for flags that differ between npm, yarn and pnpm.
+    ///
+    /// The flags are passed through verbatim and only apply to running
+    /// scripts, not to installing packages or other commands.
+    ///
+    /// [`run_verb`]: struct.Build.html#method.run_verb
+    pub fn manager_run_flags(&mut self, flags: &[&str]) -> &mut Self {
+        self.run_flags = flags.iter().map(|flag| flag.to_string()).collect();
+        self
+    }
+
+    /// Treat the given exit codes of npm scripts as success in addition to
+    /// 0, e.g. for a coverage tool that exits with 1 when below its
+    /// threshold.
+    ///
+    /// This only applies to running scripts, not to installing packages.
+    /// A script terminated by a signal has no exit code on Unix and always
+    /// counts as failed.
+    pub fn allowed_exit_codes(&mut self, codes: &[i32]) -> &mut Self {
+        self.allowed_exit_codes = codes.to_vec();
+        self
+    }
+
+    /// Run the npm script `script_name` if running a script fails, e.g. to
+    /// reset generated state, before the original error is returned.
+    ///
+    /// This covers failures while installing packages, of the script itself
+    /// and of the checks after it, but not those before npm could be found.
+    /// If the cleanup script fails as well, this is printed as a
+    /// `cargo:warning` and the original error is still returned.
+    ///
+    /// Scripts run with [`run_script_allow_failure`] that fail do not
+    /// trigger the cleanup, as the build continues.
+    ///
+    /// [`run_script_allow_failure`]: struct.Build.html#method.run_script_allow_failure
+    pub fn on_failure_script(&mut self, script_name: &str) -> &mut Self {
+        self.failure_script = Some(script_name.into());
+        self
+    }
+
+    /// Runs the script set with [`on_failure_script`], if any, reporting
+    /// its failure as a warning.
+    ///
+    /// [`on_failure_script`]: struct.Build.html#method.on_failure_script
+    fn run_failure_script(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+        node_env: &NodeEnv,
+    ) {
+        let script = match self.failure_script.clone() {
+            Some(script) => script,
+            None => return,
+        };
+        let mut cleanup = self.script_command(npm, envs, node_env, &script);
+        match self.status(&mut cleanup) {
+            Ok(status) if status.success() => {}
+            Ok(status) => println!(
+                "cargo:warning=Cleanup script {} finished with a non 0 exit code ({})",
+                script, status
+            ),
+            Err(err) => println!(
+                "cargo:warning=Could not run cleanup script {}: {}",
+                script, err
+            ),
+        }
+    }
+
+    /// Kill npm and fail with [`BuildError::TimedOut`] if installing
+    /// packages or running a script takes longer than `timeout`. The limit
+    /// applies to each npm command separately.
+    ///
+    /// On Unix npm is started in its own process group, as with
+    /// [`handle_interrupts`], so the processes it started are killed as
+    /// well. Unlike [`handle_interrupts`] this does not change how the
+    /// build script handles SIGINT or SIGTERM. Elsewhere only npm itself is
+    /// killed. Commands run by
+    /// [`run_scripts_parallel`] or in a pseudo terminal with [`use_pty`]
+    /// are not limited.
+    ///
+    /// [`BuildError::TimedOut`]: enum.BuildError.html#variant.TimedOut
+    /// [`handle_interrupts`]: struct.Build.html#method.handle_interrupts
+    /// [`run_scripts_parallel`]: struct.Build.html#method.run_scripts_parallel
+    /// [`use_pty`]: struct.Build.html#method.use_pty
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(Timeout::Fixed(timeout));
+        self
+    }
+
+    /// Like [`timeout`], but read the timeout in seconds from the
+    /// environment variable `var`, e.g. `NPM_RS_TIMEOUT_SECS`, when npm is
+    /// run, falling back to `default` if it is not set. This lets CI
+    /// systems adjust the timeout without changing the build script.
+    ///
+    /// Fractional seconds like `2.5` are accepted. Any other value fails
+    /// with [`BuildError::InvalidTimeout`] instead of using the default.
+    ///
+    /// [`timeout`]: struct.Build.html#method.timeout
+    /// [`BuildError::InvalidTimeout`]: enum.BuildError.html#variant.InvalidTimeout
+    pub fn timeout_from_env(&mut self, var: &str, default: Duration) -> &mut Self {
+        self.timeout = Some(Timeout::FromEnv {
+            var: var.into(),
+            default,
+        });
+        self
+    }
+
+    fn resolved_timeout(&self) -> Result<Option<Duration>, BuildError> {
+        match &self.timeout {
+            None => Ok(None),
+            Some(Timeout::Fixed(timeout)) => Ok(Some(*timeout)),
+            Some(Timeout::FromEnv { var, default }) => match env::var_os(var) {
+                None => Ok(Some(*default)),
+                Some(value) => {
+                    let value = value.to_string_lossy();
+                    value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .map(Some)
+                        .ok_or_else(|| BuildError::InvalidTimeout {
+                            var: var.clone(),
+                            value: value.into_owned(),
+                        })
+                }
+            },
+        }
+    }
+
+    /// Run npm through `shell -c "npm ..."` instead of starting it directly,
+    /// for systems where npm is only available after the shell set up the
+    /// environment.
+    ///
+    /// Unless [`npm_path`] is set, npm is not looked up by this crate but
+    /// left to the shell. Every argument is quoted with single quotes, so
+    /// the shell must follow POSIX quoting rules and accept `-c`, as `sh`,
+    /// `bash` and `zsh` do. On Windows this rules out `cmd.exe` and
+    /// PowerShell, but works with e.g. Git Bash. Note that non-interactive
+    /// shells usually do not read profile files such as `~/.bashrc`, so a
+    /// wrapper script may be needed to load tools like nvm.
+    ///
+    /// [`configure_command`] closures are applied to the npm command before
+    /// it is wrapped, stdio settings made there are ignored.
+    ///
+    /// [`npm_path`]: struct.Build.html#method.npm_path
+    /// [`configure_command`]: struct.Build.html#method.configure_command
+    pub fn shell<P: AsRef<Path>>(&mut self, shell: P) -> &mut Self {
+        self.shell = Some(shell.as_ref().into());
+        self
+    }
+
+    /// The path of the npm executable used by this `Build`, or `None` if
+    /// nothing was run yet and no path was set with [`npm_path`].
+    ///
+    /// npm is looked up in `PATH` once, on the first run, and reused for
+    /// every later run.
+    ///
+    /// Useful for logging or for running follow-up commands with the same
+    /// npm installation.
+    ///
+    /// [`npm_path`]: struct.Build.html#method.npm_path
+    pub fn resolved_npm_path(&self) -> Option<&Path> {
+        self.npm.as_deref()
+    }
+
+    fn resolve_npm(&mut self) -> Result<PathBuf, BuildError> {
+        if let Some(npm) = &self.npm {
+            return Ok(npm.clone());
+        }
+        if self.detect_manager {
+            self.manager = self.detected_manager();
+            let name = self.manager_name().to_string();
+            if name != "npm" {
+                if self.shell.is_some() {
+                    return Ok(name.into());
+                }
+                let manager = (self.lookup)(&name).ok_or_else(|| {
+                    BuildError::PackageManagerNotFound(self.manager.clone().unwrap_or(name))
+                })?;
+                self.npm = Some(manager.clone());
+                return Ok(manager);
+            }
+        }
+        if self.shell.is_some() {
+            return Ok("npm".into());
+        }
+        let npm = self.find_npm()?;
+        self.npm = Some(npm.clone());
+        Ok(npm)
+    }
+
+    /// The directory npm is run in. This is the [`target_directory`], or
+    /// the current directory if none was set.
+    ///
+    /// Useful for locating generated files in the build script after a
+    /// run. With [`use_temp_target`] this is the temporary directory once
+    /// it was created by the first run.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`use_temp_target`]: struct.Build.html#method.use_temp_target
+    pub fn effective_target(&self) -> &Path {
+        match &self.temp_target {
+            Some(temp) => temp.path(),
+            None => or_current_dir(&self.target_directory),
+        }
+    }
+
+    /// Whether the most recent run installed node packages. This is `false`
+    /// if packages were already installed by an earlier run or installing
+    /// was skipped.
+    pub fn did_install(&self) -> bool {
+        self.did_install
+    }
+
+    /// A summary of every npm command run by this `Build` so far.
+    pub fn report(&self) -> RunReport {
+        RunReport {
+            package: package_name(&self.manifest_path()).ok().flatten(),
+            package_manager: self.manager.clone().unwrap_or_else(|| "npm".into()),
+            npm_path: self.npm.clone(),
+            commands: self.commands.clone(),
+            outputs: self.declared_outputs(),
+            attempts: self.attempts,
+            targets: self.target_reports.clone(),
+        }
+    }
+
+    /// Print how long each command took as a `cargo:warning`, e.g.
+    /// `npm 'build' took 12.3s`, to see what slows down the build.
+    /// Installing packages is reported as `'install'` or `'ci'`. Defaults to
+    /// `false`.
+    ///
+    /// The same durations are available in the [`report`].
+    ///
+    /// [`report`]: struct.Build.html#method.report
+    pub fn report_timing(&mut self, enable: bool) -> &mut Self {
+        self.report_timing = enable;
+        self
+    }
+
+    /// The [`report`] rendered as JSON, for tools wrapping the build script.
+    ///
+    /// Requires the `serde` feature.
+    ///
+    /// [`report`]: struct.Build.html#method.report
+    #[cfg(feature = "serde")]
+    pub fn report_json(&self) -> String {
+        serde_json::to_string_pretty(&self.report()).expect("Could not serialize report")
+    }
+
+    /// The `package.json` in the project directory.
+    fn manifest_path(&self) -> PathBuf {
+        or_current_dir(&self.project_directory).join("package.json")
+    }
+
+    /// A human readable summary of the effective configuration, useful for
+    /// debugging or when reporting a bug.
+    pub fn describe(&self) -> String {
+        let display_items = |items: &[PathBuf]| {
+            items
+                .iter()
+                .map(|item| item.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let copy = match &self.copy {
+            CopyItems::Nothing => "nothing".to_string(),
+            CopyItems::All => format!(
+                "everything except node_modules{}{}",
+                if self.exclude_hidden {
+                    " and hidden files"
+                } else {
+                    ""
+                },
+                if self.copy_once {
+                    " if the target is empty".to_string()
+                } else if let Some(git_ref) = &self.changed_since {
+                    format!(", then files changed since {}", git_ref)
+                } else {
+                    String::new()
+                }
+            ),
+            CopyItems::Some(items) => display_items(items),
+            CopyItems::Sync(items) => format!("{} (removing other files)", display_items(items)),
+        };
+        let install = if self.install_disabled {
+            "disabled".to_string()
+        } else if self.install_skipped {
+            "skipped for the next run".to_string()
+        } else {
+            format!("{} {}", self.manager_name(), self.install_args().join(" "))
+        };
+        let npm = match &self.npm {
+            Some(npm) => npm.display().to_string(),
+            None => "looked up in PATH".to_string(),
+        };
+        let package = match package_name(&self.manifest_path()) {
+            Ok(Some(name)) => name,
+            Ok(None) => "unnamed".to_string(),
+            Err(_) => "unknown".to_string(),
+        };
+        let planned = if self.planned.is_empty() {
+            "none".to_string()
+        } else {
+            let names: Vec<_> = self.planned.iter().map(|planned| &*planned.name).collect();
+            names.join(", ")
+        };
+        let targets = if self.targets.is_empty() {
+            "none".to_string()
+        } else {
+            self.targets
+                .iter()
+                .map(|target| format!("{} ({})", target.name, target.script))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "package: {}\n\
+             project directory: {}\n\
+             target directory: {}\n\
+             copy: {}\n\
+             NODE_ENV: {}\n\
+             install: {}\n\
+             package manager: {} ({})\n\
+             planned scripts: {}\n\
+             targets: {}\n",
+            package,
+            or_current_dir(&self.project_directory).display(),
+            self.effective_target().display(),
+            copy,
+            self.node_env.to_env_var(),
+            install,
+            self.manager.as_deref().unwrap_or("npm"),
+            npm,
+            planned,
+            targets
+        )
+    }
+
+    /// Checks the configuration without copying, installing or running
+    /// anything and returns the first problem found.
+    ///
+    /// This checks that
+    /// * the [`project_directory`] exists and contains a `package.json`,
+    /// * items to be copied exist and are relative paths,
+    /// * copying does not move one directory into the other,
+    /// * npm can be found on this machine.
+    ///
+    /// Use [`validate_all`] to get every problem at once.
+    ///
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`validate_all`]: struct.Build.html#method.validate_all
+    pub fn validate(&self) -> Result<(), BuildError> {
+        self.validate_all().map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Like [`validate`], but reports every problem found instead of
+    /// only the first one.
+    ///
+    /// [`validate`]: struct.Build.html#method.validate
+    pub fn validate_all(&self) -> Result<(), Vec<BuildError>> {
+        let mut errors = Vec::new();
+        let project = or_current_dir(&self.project_directory);
+        if !project.is_dir() {
+            errors.push(BuildError::ProjectDirectoryNotFound(project.into()));
+        } else {
+            if !project.join("package.json").is_file() {
+                errors.push(BuildError::MissingPackageJson(project.into()));
+            }
+            if self.copies_items() {
+                errors.extend(self.copy_errors(project));
+            }
+        }
+        match &self.npm {
+            Some(npm) if !npm.is_file() => errors.push(BuildError::NpmNotFound {
+                path: env::var_os("PATH"),
+                searched: vec![npm.clone()],
+            }),
+            None if self.shell.is_none() => {
+                if let Err(err) = self.find_npm() {
+                    errors.push(err);
+                }
+            }
+            _ => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The items to copy, with environment variables expanded if enabled.
+    fn resolved_copy(&self) -> Result<CopyItems, BuildError> {
+        if let CopyItems::All = self.copy {
+            if self.exclude_hidden || self.deterministic_order {
+                let project = or_current_dir(&self.project_directory);
+                let mut items = get_folder_contents(project, self.exclude_hidden)?;
+                if self.deterministic_order {
+                    items.sort();
+                }
+                return Ok(CopyItems::Some(items));
+            }
+        }
+        if !self.expand_env_in_paths {
+            return Ok(self.copy.clone());
+        }
+        let expand = |items: &[PathBuf]| {
+            items
+                .iter()
+                .map(|item| expand_env_vars(item))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(match &self.copy {
+            CopyItems::Some(items) => CopyItems::Some(expand(items)?),
+            CopyItems::Sync(items) => CopyItems::Sync(expand(items)?),
+            other => other.clone(),
+        })
+    }
+
+    fn copy_errors(&self, project: &Path) -> Vec<BuildError> {
+        let copy = match self.resolved_copy() {
+            Ok(copy) => copy,
+            Err(err) => return vec![err],
+        };
+        let items = match copy {
+            CopyItems::Nothing => return vec![BuildError::NothingToCopy],
+            CopyItems::All => match get_folder_contents(project, false) {
+                Ok(items) => items,
+                Err(err) => return vec![err.into()],
+            },
+            CopyItems::Some(items) | CopyItems::Sync(items) => items,
+        };
+        let mut errors = Vec::new();
+        for item in &items {
+            if item.is_absolute() {
+                errors.push(BuildError::AbsoluteCopyItem(item.clone()));
+            } else if !project.join(item).exists() {
+                errors.push(BuildError::CopyItemNotFound(item.clone()));
+            }
+        }
+        if directories_overlap(project, &self.target_directory, &items) {
+            errors.push(BuildError::DirectoriesOverlap {
+                project: project.into(),
+                target: self.target_directory.clone(),
+            });
+        }
+        errors
+    }
+
+    /// Collects the environment variables for spawned npm processes, in
+    /// the order they should be applied.
+    fn environment(&self) -> Result<Vec<(OsString, OsString)>, BuildError> {
+        let mut vars = Vec::new();
+        #[cfg(feature = "dotenv")]
+        for path in &self.env_files {
+            let invalid = |source| BuildError::InvalidEnvFile {
+                path: path.clone(),
+                source,
+            };
+            for item in dotenvy::from_path_iter(path).map_err(invalid)? {
+                let (key, value) = item.map_err(invalid)?;
+                vars.push((key.into(), value.into()));
+            }
+        }
+        if let Some(name) = &self.version_env {
+            let version = env::var_os("CARGO_PKG_VERSION")
+                .ok_or_else(|| BuildError::UndefinedEnvVar("CARGO_PKG_VERSION".into()))?;
+            vars.push((name.into(), version));
+        }
+        if let Some(name) = &self.target_triple_env {
+            let target = env::var_os("TARGET")
+                .ok_or_else(|| BuildError::UndefinedEnvVar("TARGET".into()))?;
+            vars.push((name.into(), target));
+        }
+        let configs = [
+            ("npm_config_userconfig", &self.user_config),
+            ("npm_config_globalconfig", &self.global_config),
+        ];
+        for (name, path) in configs {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    return Err(BuildError::MissingConfigFile(path.clone()));
+                }
+                vars.push((name.into(), absolute_path(path).into()));
+            }
+        }
+        if let Some(dir) = &self.home_dir {
+            let dir = absolute_path(dir);
+            create_dir_all(&dir)?;
+            if cfg!(windows) {
+                vars.push(("USERPROFILE".into(), dir.clone().into()));
+            }
+            vars.push(("HOME".into(), dir.into()));
+        }
+        vars.extend(
+            self.envs
+                .iter()
+                .map(|(key, value)| (key.clone(), value.0.clone())),
+        );
+        if !self.path_prepend.is_empty() {
+            let inherited = vars
+                .iter()
+                .rev()
+                .find(|(key, _)| key == "PATH")
+                .map(|(_, value)| value.clone())
+                .or_else(|| env::var_os("PATH"))
+                .unwrap_or_default();
+            let dirs = self
+                .path_prepend
+                .iter()
+                .cloned()
+                .chain(env::split_paths(&inherited));
+            let path = env::join_paths(dirs).map_err(BuildError::InvalidPath)?;
+            vars.push(("PATH".into(), path));
+        }
+        Ok(vars)
+    }
+
+    fn command(&self, npm: &Path, envs: &[(OsString, OsString)], node_env: &NodeEnv) -> Command {
+        let mut command = Command::new(npm);
+        if let Some(keys) = &self.env_allowlist {
+            command.env_clear();
+            for key in keys {
+                if let Some(value) = env::var_os(key) {
+                    command.env(key, value);
+                }
+            }
+        }
+        command
+            .envs(envs.iter().map(|(k, v)| (k, v)))
+            .env("NODE_ENV", node_env.to_env_var())
+            .current_dir(self.effective_target());
+        command
+    }
+
+    /// Runs `command` to completion, applying the configured output
+    /// redirection, and records it for the report.
+    fn status(&mut self, command: &mut Command) -> Result<ExitStatus, BuildError> {
+        let start = Instant::now();
+        let status = match self.shell.clone() {
+            Some(shell) => {
+                self.apply_hooks(command);
+                let mut wrapped = through_shell(&shell, command, self.env_allowlist.is_some());
+                self.redirect_to_log(&mut wrapped)?;
+                self.apply_limits(&mut wrapped);
+                self.wait(&mut wrapped)?
+            }
+            None => {
+                self.redirect_to_log(command)?;
+                self.apply_hooks(command);
+                self.apply_limits(command);
+                self.wait(command)?
+            }
+        };
+        self.record(command, start, status);
+        Ok(status)
+    }
+
+    /// Runs `command` to completion like `status`, but captures and
+    /// returns its stdout, and its stderr if `capture_stderr` is set,
+    /// instead of passing them through.
+    fn output(
+        &mut self,
+        command: &mut Command,
+        capture_stderr: bool,
+    ) -> Result<Output, BuildError> {
+        self.apply_hooks(command);
+        let mut wrapped = self
+            .shell
+            .as_ref()
+            .map(|shell| through_shell(shell, command, self.env_allowlist.is_some()));
+        let spawned = wrapped.as_mut().unwrap_or(command);
+        self.redirect_to_log(spawned)?;
+        spawned.stdin(Stdio::null()).stdout(Stdio::piped());
+        if capture_stderr {
+            spawned.stderr(Stdio::piped());
+        }
+        self.apply_limits(spawned);
+        let start = Instant::now();
+        let (child, _tracked) = self.spawn(spawned)?;
+        let output = child.wait_with_output().map_err(BuildError::SpawnFailed)?;
+        self.record(command, start, output.status);
+        Ok(output)
+    }
+
+    fn redirect_to_log(&mut self, command: &mut Command) -> Result<(), BuildError> {
+        if let Some((stdout, stderr)) = &self.log {
+            let truncate = !self.log_opened;
+            command
+                .stdout(open_log(stdout, truncate)?)
+                .stderr(open_log(stderr, truncate)?);
+            self.log_opened = true;
+        }
+        Ok(())
+    }
+
+    fn wait(&self, command: &mut Command) -> Result<ExitStatus, BuildError> {
+        #[cfg(feature = "pty")]
+        {
+            if self.use_pty {
+                return match &self.log {
+                    Some((stdout, _)) => pty::status(command, &mut open_log(stdout, false)?),
+                    None => pty::status(command, &mut io::stdout()),
+                };
+            }
+        }
+        let timeout = self.resolved_timeout()?;
+        if self.line_hook.is_some() || self.output_prefix.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        // Killing on timeout needs the process group, but only
+        // handle_interrupts may install signal handlers
+        let (mut child, _tracked) = if timeout.is_some() && !self.handle_interrupts {
+            let child = interrupts::spawn_group(command).map_err(BuildError::SpawnFailed)?;
+            (child, None)
+        } else {
+            self.spawn(command)?
+        };
+        let readers = self.stream_lines(&mut child)?;
+        let status = match timeout {
+            Some(timeout) => wait_with_timeout(&mut child, timeout),
+            None => child.wait().map_err(BuildError::SpawnFailed),
+        };
+        for reader in readers {
+            reader.join().expect("Thread reading npm output panicked")?;
+        }
+        status
+    }
+
+    /// Starts threads passing each line `child` prints to the
+    /// [`on_output_line`] closure and adding the [`output_prefix`], if
+    /// either is set.
+    ///
+    /// [`on_output_line`]: struct.Build.html#method.on_output_line
+    /// [`output_prefix`]: struct.Build.html#method.output_prefix
+    fn stream_lines(&self, child: &mut Child) -> Result<Vec<LineReader>, BuildError> {
+        if self.line_hook.is_none() && self.output_prefix.is_none() {
+            return Ok(Vec::new());
+        }
+        let (stdout, stderr): (Box<dyn Write + Send>, Box<dyn Write + Send>) = match &self.log {
+            Some((stdout, stderr)) => (
+                Box::new(open_log(stdout, false)?),
+                Box::new(open_log(stderr, false)?),
+            ),
+            None => (Box::new(io::stdout()), Box::new(io::stderr())),
+        };
+        let forward = self.line_hook.is_none() || self.forward_output_lines;
+        let prefix = &self.output_prefix;
+        let hook = &self.line_hook;
+        let mut readers = Vec::new();
+        if let Some(pipe) = child.stdout.take() {
+            readers.push(read_lines(
+                pipe,
+                stdout,
+                forward,
+                prefix.clone(),
+                hook.clone(),
+            ));
+        }
+        if let Some(pipe) = child.stderr.take() {
+            readers.push(read_lines(
+                pipe,
+                stderr,
+                forward,
+                prefix.clone(),
+                hook.clone(),
+            ));
+        }
+        Ok(readers)
+    }
+
+    /// Starts `command`, tracking it for [`handle_interrupts`] if enabled.
+    ///
+    /// [`handle_interrupts`]: struct.Build.html#method.handle_interrupts
+    fn spawn(&self, command: &mut Command) -> Result<(Child, Option<Tracked>), BuildError> {
+        if self.handle_interrupts {
+            let (child, tracked) = interrupts::spawn(command).map_err(BuildError::SpawnFailed)?;
+            return Ok((child, Some(tracked)));
+        }
+        let child = command.spawn().map_err(BuildError::SpawnFailed)?;
+        Ok((child, None))
+    }
+
+    fn apply_hooks(&self, command: &mut Command) {
+        for hook in &self.command_hooks {
+            (hook.0)(command);
+        }
+    }
+
+    fn apply_limits(&self, command: &mut Command) {
+        if let Some(limits) = self.resource_limits {
+            limits::apply(command, limits);
+        }
+        if let Some(mask) = self.umask {
+            limits::apply_umask(command, mask);
+        }
+    }
+
+    /// Adds a finished command to the report.
+    fn record(&mut self, command: &Command, start: Instant, status: ExitStatus) {
+        let duration = start.elapsed();
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        if self.report_timing {
+            let phase = self.script_of(&args).or(args.first().map(String::as_str));
+            println!(
+                "cargo:warning={} '{}' took {:.1}s",
+                Path::new(command.get_program())
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                phase.unwrap_or_default(),
+                duration.as_secs_f64()
+            );
+        }
+        let node_env = command
+            .get_envs()
+            .find(|(key, _)| *key == "NODE_ENV")
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.commands.push(CommandReport {
+            args,
+            node_env,
+            duration,
+            exit_code: status.code(),
+        });
+    }
+
+    /// The script run by a command with `args`, or `None` if it does not
+    /// run a script with the [`run_verb`].
+    ///
+    /// [`run_verb`]: struct.Build.html#method.run_verb
+    fn script_of<'a>(&self, args: &'a [String]) -> Option<&'a str> {
+        let args = args.strip_prefix(self.run_flags.as_slice()).unwrap_or(args);
+        match (&self.run_verb, args) {
+            (Some(verb), [first, script, ..]) if first == verb => Some(script),
+            _ => None,
+        }
+    }
+
+    /// Writes output captured from a command to the log files, or to the
+    /// output of the build script if no log files are configured.
+    fn write_output(&mut self, output: &Output) -> Result<(), BuildError> {
+        let prefixed;
+        let output = match &self.output_prefix {
+            Some(prefix) => {
+                prefixed = Output {
+                    status: output.status,
+                    stdout: prefix_lines(prefix, &output.stdout),
+                    stderr: prefix_lines(prefix, &output.stderr),
+                };
+                &prefixed
+            }
+            None => output,
+        };
+        match &self.log {
+            Some((stdout, stderr)) => {
+                let truncate = !self.log_opened;
+                self.log_opened = true;
+                let write = |path: &Path, data: &[u8]| {
+                    open_log(path, truncate)?.write_all(data).map_err(|source| {
+                        BuildError::CannotOpenLog {
+                            path: path.into(),
+                            source,
+                        }
+                    })
+                };
+                write(stdout, &output.stdout)?;
+                write(stderr, &output.stderr)
+            }
+            None => {
+                io::stdout().write_all(&output.stdout)?;
+                io::stderr().write_all(&output.stderr)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles the captured `output` of a command like streamed output:
+    /// passes its lines to the [`on_output_line`] closure and writes it
+    /// with `write_output` unless forwarding was disabled.
+    ///
+    /// [`on_output_line`]: struct.Build.html#method.on_output_line
+    fn replay_output(&mut self, output: &Output) -> Result<(), BuildError> {
+        if let Some(hook) = &self.line_hook {
+            let mut hook = hook.lock().unwrap_or_else(PoisonError::into_inner);
+            for data in [&output.stdout, &output.stderr] {
+                for line in String::from_utf8_lossy(data).lines() {
+                    (hook.0)(line);
+                }
+            }
+        }
+        if self.line_hook.is_none() || self.forward_output_lines {
+            self.write_output(output)?;
+        }
+        Ok(())
+    }
+
+    fn script_command(
+        &self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+        node_env: &NodeEnv,
+        script_name: &str,
+    ) -> Command {
+        let mut command = self.command(npm, envs, node_env);
+        command
+            .args(&self.run_flags)
+            .args(&self.run_verb)
+            .arg(script_name);
+        command
+    }
+
+    fn script_succeeded(&self, status: ExitStatus) -> bool {
+        status.success()
+            || status
+                .code()
+                .is_some_and(|code| self.allowed_exit_codes.contains(&code))
+    }
+
+    fn install_args(&self) -> Vec<String> {
+        let cmd = if self.fast_sync {
+            if self.has_lockfile() {
+                "ci"
+            } else {
+                "install"
+            }
+        } else if is_release() && (self.has_lockfile() || self.require_lockfile != Some(false)) {
+            "ci"
+        } else {
+            "install"
+        };
+        let mut args = vec![cmd.to_string()];
+        if cmd == "ci" && self.manager_name() != "npm" {
+            let berry = matches!(
+                self.manager.as_deref(),
+                Some(manager) if manager.starts_with("yarn@") && !manager.starts_with("yarn@1.")
+            );
+            args = vec![
+                "install".to_string(),
+                if berry {
+                    "--immutable"
+                } else {
+                    "--frozen-lockfile"
+                }
+                .to_string(),
+            ];
+        }
+        if self.fast_sync {
+            args.push("--prefer-offline".to_string());
+        }
+        if let Some(n) = self.max_sockets {
+            args.push(format!("--maxsockets={}", n));
+        }
+        if self.no_audit {
+            args.push("--no-audit".to_string());
+        }
+        if self.no_fund {
+            args.push("--no-fund".to_string());
+        }
+        if self.no_progress {
+            args.push("--no-progress".to_string());
+        }
+        if self.no_save {
+            args.push("--no-save".to_string());
+        }
+        if self.foreground_scripts {
+            args.push("--foreground-scripts".to_string());
+        }
+        if self.prefer_dedupe {
+            args.push("--prefer-dedupe".to_string());
+        }
+        if let Some(strategy) = &self.install_strategy {
+            args.push(format!("--install-strategy={}", strategy));
+        }
+        if !self.install_script_args.is_empty() {
+            args.push("--".to_string());
+            args.extend(self.install_script_args.iter().cloned());
+        }
+        args
+    }
+
+    /// Run an npm script with the given `script_name`.
+    ///
+    /// Before running the script this function copies files from
+    /// [`project_directory`] to [`target_directory`] if necessary and
+    /// installs node packages.
+    ///
+    /// It uses `npm ci` if building with `--release`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`try_run_script`] returns an error, with the error's
+    /// message.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn run_script(&mut self, script_name: &str) -> &mut Self {
+        self.try_run_script(script_name)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like [`run_script`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// * [`BuildError::NothingToCopy`] if [`target_directory`] is different
+    ///   from [`project_directory`] but neither [`copy_items`] nor
+    ///   [`copy_all`] was called.
+    /// * [`BuildError::NpmNotFound`] if npm cannot be found on this machine.
+    /// * [`BuildError::InstallFailed`] if either `npm install`/`npm ci`
+    ///   returns with an error.
+    /// * [`BuildError::ScriptFailed`] if the executed npm script returns
+    ///   with an error.
+    /// * [`BuildError::CannotCreateTarget`] if the target directory cannot
+    ///   be created.
+    /// * Other variants if copying files or starting npm fails.
+    ///
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`copy_items`]: struct.Build.html#method.copy_items
+    /// [`copy_all`]: struct.Build.html#method.copy_all
+    /// [`BuildError::NothingToCopy`]: enum.BuildError.html#variant.NothingToCopy
+    /// [`BuildError::NpmNotFound`]: enum.BuildError.html#variant.NpmNotFound
+    /// [`BuildError::InstallFailed`]: enum.BuildError.html#variant.InstallFailed
+    /// [`BuildError::ScriptFailed`]: enum.BuildError.html#variant.ScriptFailed
+    /// [`BuildError::CannotCreateTarget`]: enum.BuildError.html#variant.CannotCreateTarget
+    pub fn try_run_script(&mut self, script_name: &str) -> Result<&mut Self, BuildError> {
+        self.plan_script(script_name).execute()?;
+        Ok(self)
+    }
+
+    /// Copy the selected items to the [`target_directory`] and tell Cargo
+    /// to rerun the build script when they change, without installing
+    /// packages or running npm at all, e.g. when npm is run elsewhere.
+    ///
+    /// A `cargo:rerun-if-changed` line is printed for each copied item in
+    /// the [`project_directory`], or for `package.json` and
+    /// `package-lock.json` if nothing is copied. A later run of a script
+    /// reuses the copied files instead of copying them again.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`] if the preflight checks or copying
+    /// fail.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn prepare_only(&mut self) -> Result<(), BuildError> {
+        if self.is_skipped(&self.node_env) {
+            return Ok(());
+        }
+        let lock = self.lock_installs()?;
+        self.prepare_files()?;
+        drop(lock);
+        let project = or_current_dir(&self.project_directory);
+        let watched = match self.resolved_copy()? {
+            _ if !self.copies_items() => vec!["package.json".into(), "package-lock.json".into()],
+            CopyItems::Nothing => Vec::new(),
+            CopyItems::All => get_folder_contents(project, false)?,
+            CopyItems::Some(items) | CopyItems::Sync(items) => items,
+        };
+        for item in watched {
+            let path = project.join(item);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that the npm script `script_name` should run, without running
+    /// anything yet. Planned scripts run in order on the next call to
+    /// [`execute`].
+    ///
+    /// Separating the plan from its execution allows configuring a whole
+    /// build before checking it with [`validate`] or [`describe`].
+    ///
+    /// [`execute`]: struct.Build.html#method.execute
+    /// [`validate`]: struct.Build.html#method.validate
+    /// [`describe`]: struct.Build.html#method.describe
+    pub fn plan_script(&mut self, script_name: &str) -> &mut Self {
+        self.planned.push(PlannedScript::new(script_name));
+        self
+    }
+
+    /// The scripts recorded with [`plan_script`] that have not run yet.
+    ///
+    /// [`plan_script`]: struct.Build.html#method.plan_script
+    pub fn planned_scripts(&self) -> Vec<&str> {
+        self.planned.iter().map(|planned| &*planned.name).collect()
+    }
+
+    /// Register the target `name`, built by running the npm script `script`
+    /// with the environment variables `env` on the next call to
+    /// [`execute`], e.g. an `es` and a `cjs` bundle built by the same
+    /// script.
+    ///
+    /// Like planned scripts, targets are built once: the next [`execute`],
+    /// or [`try_run_script`] which executes the plan, consumes them, and
+    /// later scripts do not build them again.
+    ///
+    /// Targets share the copied files and installed node packages. Each
+    /// gets its own output directory `targets/<name>` in the directory npm
+    /// is run in, created before its script runs and passed to it as the
+    /// absolute path in `NPM_RS_TARGET_DIR`, along with its name in
+    /// `NPM_RS_TARGET`.
+    ///
+    /// [`execute`]: struct.Build.html#method.execute
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn add_target(&mut self, name: &str, script: &str, env: Vec<(&str, &str)>) -> &mut Self {
+        self.targets.push(Target {
+            name: name.into(),
+            script: script.into(),
+            env: env
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        });
+        self
+    }
+
+    /// The targets registered with [`add_target`] that have not been built
+    /// yet.
+    ///
+    /// [`add_target`]: struct.Build.html#method.add_target
+    pub fn targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Copy files, install node packages and run every script recorded with
+    /// [`plan_script`], in order, then build every target registered with
+    /// [`add_target`] and return the [`report`] of everything run by this
+    /// `Build`.
+    ///
+    /// Without planned scripts or targets this only copies files and
+    /// installs packages. [`try_run_script`] plans a single script and
+    /// executes the plan right away, including scripts planned before.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`], stopping at the first script that
+    /// fails. The remaining planned scripts and the targets are discarded.
+    /// A failing target
+    /// does not stop the other targets, and the build fails with
+    /// [`BuildError::TargetsFailed`] once all of them ran. The outcome of
+    /// each target is in the [`report`].
+    ///
+    /// [`plan_script`]: struct.Build.html#method.plan_script
+    /// [`add_target`]: struct.Build.html#method.add_target
+    /// [`BuildError::TargetsFailed`]: enum.BuildError.html#variant.TargetsFailed
+    /// [`report`]: struct.Build.html#method.report
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn execute(&mut self) -> Result<RunReport, BuildError> {
+        let node_env = self.node_env.clone();
+        let planned = std::mem::take(&mut self.planned);
+        let targets = std::mem::take(&mut self.targets);
+        self.retrying(|build| build.execute_planned(&planned, &targets, &node_env))?;
+        Ok(self.report())
+    }
+
+    /// Runs `attempt` until it succeeds or the attempts set with
+    /// [`retry`] are used up.
+    ///
+    /// [`retry`]: struct.Build.html#method.retry
+    fn retrying<F>(&mut self, mut attempt: F) -> Result<(), BuildError>
+    where
+        F: FnMut(&mut Self) -> Result<(), BuildError>,
+    {
+        self.attempts = 1;
+        while let Err(err) = attempt(self) {
+            if self.attempts > self.retries {
+                return Err(err);
+            }
+            println!(
+                "cargo:warning=npm-rs: attempt {} failed, retrying: {}",
+                self.attempts, err
+            );
+            self.installed = false;
+            let node_modules = self.effective_target().join("node_modules");
+            if self.clean_on_retry && node_modules.is_dir() {
+                fs::remove_dir_all(node_modules)?;
+            }
+            self.attempts += 1;
+        }
+        Ok(())
+    }
+
+    fn execute_planned(
+        &mut self,
+        planned: &[PlannedScript],
+        targets: &[Target],
+        node_env: &NodeEnv,
+    ) -> Result<(), BuildError> {
+        if planned.is_empty() && targets.is_empty() && !self.is_skipped(node_env) {
+            self.check_node_env(node_env)?;
+            let npm = self.resolve_npm()?;
+            let envs = self.environment()?;
+            self.prepare(&npm, &envs)?;
+        }
+        for planned in planned {
+            let node_env = planned.node_env.as_ref().unwrap_or(node_env);
+            let result = self.run_script_checked(
+                &planned.name,
+                &planned.args,
+                node_env,
+                planned.allow_failure,
+            );
+            match result {
+                Err(BuildError::ScriptFailed { script, status }) if planned.allow_failure => {
+                    self.failed_scripts.push((script, status))
+                }
+                result => result?,
+            }
+        }
+        if !targets.is_empty() && !self.is_skipped(node_env) {
+            self.build_targets(targets, node_env)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the script of every target in `targets`, recording the
+    /// outcomes for the report, see [`add_target`].
+    ///
+    /// [`add_target`]: struct.Build.html#method.add_target
+    fn build_targets(&mut self, targets: &[Target], node_env: &NodeEnv) -> Result<(), BuildError> {
+        self.check_node_env(node_env)?;
+        let npm = self.resolve_npm()?;
+        let envs = self.environment()?;
+        self.prepare(&npm, &envs)?;
+        self.target_reports.clear();
+        for target in targets {
+            let directory =
+                absolute_path(&self.effective_target().join("targets").join(&target.name));
+            let mut target_envs = envs.clone();
+            target_envs.push(("NPM_RS_TARGET".into(), target.name.clone().into()));
+            target_envs.push(("NPM_RS_TARGET_DIR".into(), directory.clone().into()));
+            target_envs.extend(
+                target
+                    .env
+                    .iter()
+                    .map(|(key, value)| (key.into(), value.into())),
+            );
+            let result = create_dir_all(&directory)
+                .map_err(BuildError::from)
+                .and_then(|()| {
+                    self.run_prepared_script(&npm, &target_envs, &target.script, &[], node_env)
+                });
+            if result.is_err() {
+                self.run_failure_script(&npm, &target_envs, node_env);
+            }
+            self.target_reports.push(TargetReport {
+                name: target.name.clone(),
+                directory,
+                error: result.err().map(|err| err.to_string()),
+            });
+        }
+        let failed: Vec<_> = self
+            .target_reports
+            .iter()
+            .filter(|report| report.error.is_some())
+            .map(|report| report.name.clone())
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(BuildError::TargetsFailed(failed))
+        }
+    }
+
+    /// Retry a failed [`execute`], [`try_run_script`] or
+    /// [`run_scripts_parallel`] up to `count` times, installing packages
+    /// again and rerunning every planned script from the start, e.g. for a
+    /// bundler that crashes now and then. Each failed
+    /// attempt is printed as a `cargo:warning`, and the [`report`] records
+    /// how many attempts were made. Defaults to 0.
+    ///
+    /// Scripts with side effects run again as a whole, so this is only safe
+    /// for scripts that can be repeated. Files are not copied again.
+    ///
+    /// [`execute`]: struct.Build.html#method.execute
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`run_scripts_parallel`]: struct.Build.html#method.run_scripts_parallel
+    /// [`report`]: struct.Build.html#method.report
+    pub fn retry(&mut self, count: u32) -> &mut Self {
+        self.retries = count;
+        self
+    }
+
+    /// Whether [`retry`] removes `node_modules` in the directory npm is run
+    /// in before each new attempt, in case a broken install caused the
+    /// failure. Defaults to `false`.
+    ///
+    /// [`retry`]: struct.Build.html#method.retry
+    pub fn clean_on_retry(&mut self, enable: bool) -> &mut Self {
+        self.clean_on_retry = enable;
+        self
+    }
+
+    /// Like [`run_script`], but a non 0 exit code of the script does not
+    /// abort the build. The failure is recorded instead and can be
+    /// inspected with [`failed_scripts`].
+    ///
+    /// This is meant for non-critical scripts such as linters. It only
+    /// affects the exit code of the script itself, copying files and
+    /// installing packages still panic on failure.
+    ///
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`failed_scripts`]: struct.Build.html#method.failed_scripts
+    pub fn run_script_allow_failure(&mut self, script_name: &str) -> &mut Self {
+        self.planned.push(PlannedScript {
+            allow_failure: true,
+            ..PlannedScript::new(script_name)
+        });
+        if let Err(err) = self.execute() {
+            panic!("{}", err);
+        }
+        self
+    }
+
+    /// The scripts run with [`run_script_allow_failure`] that failed, with
+    /// the exit status of npm.
+    ///
+    /// [`run_script_allow_failure`]: struct.Build.html#method.run_script_allow_failure
+    pub fn failed_scripts(&self) -> &[(String, ExitStatus)] {
+        &self.failed_scripts
+    }
+
+    /// Run the npm script `script_name` once for every value in `envs`,
+    /// using it as `NODE_ENV` for that run.
+    ///
+    /// Node packages are installed at most once, before the first run. The
+    /// value set with [`node_env`] is left unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`run_script`], stopping at the
+    /// first environment the script fails in and naming it.
+    ///
+    /// [`node_env`]: struct.Build.html#method.node_env
+    /// [`run_script`]: struct.Build.html#method.run_script
+    pub fn run_script_for_envs(&mut self, script_name: &str, envs: &[&str]) -> &mut Self {
+        for env in envs {
+            self.planned.push(PlannedScript {
+                node_env: Some(NodeEnv::from(*env)),
+                ..PlannedScript::new(script_name)
+            });
+            match self.execute() {
+                Ok(_) => {}
+                Err(err @ BuildError::ScriptFailed { .. }) => {
+                    panic!("{} with NODE_ENV={}", err, env)
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+        self
+    }
+
+    /// Run the npm script `script_name`, forwarding the arguments in the
+    /// environment variable `var` to it after `--`, e.g. for CI builds
+    /// passing `--mode staging` in `FRONTEND_BUILD_ARGS`.
+    ///
+    /// The value is split on whitespace, quotes are not interpreted. If
+    /// `var` is unset or empty, the script runs without extra arguments.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`].
+    ///
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn run_script_args_from_env(
+        &mut self,
+        script_name: &str,
+        var: &str,
+    ) -> Result<&mut Self, BuildError> {
+        let args: Vec<String> = env::var_os(var)
+            .map(|value| {
+                value
+                    .to_string_lossy()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.planned.push(PlannedScript {
+            args,
+            ..PlannedScript::new(script_name)
+        });
+        self.execute()?;
+        Ok(self)
+    }
+
+    /// Run the npm script `script_name`, forwarding the arguments in
+    /// `cmdline` to it after `--`, e.g. `--mode "staging eu" --watch`.
+    ///
+    /// `cmdline` is split following POSIX shell rules: single and double
+    /// quotes group words and backslashes escape the next character.
+    /// Variables, globs and other shell features are not expanded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BuildError::InvalidCommandLine`] if `cmdline` has
+    /// unbalanced quotes, otherwise fails like [`try_run_script`].
+    ///
+    /// [`BuildError::InvalidCommandLine`]: enum.BuildError.html#variant.InvalidCommandLine
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    pub fn run_script_cmdline(
+        &mut self,
+        script_name: &str,
+        cmdline: &str,
+    ) -> Result<&mut Self, BuildError> {
+        let args =
+            shlex::split(cmdline).ok_or_else(|| BuildError::InvalidCommandLine(cmdline.into()))?;
+        self.planned.push(PlannedScript {
+            args,
+            ..PlannedScript::new(script_name)
+        });
+        self.execute()?;
+        Ok(self)
+    }
+
+    /// Run an arbitrary npm `subcommand`, such as `dedupe` or `rebuild`,
+    /// with the given `args` in the [`target_directory`].
+    ///
+    /// Files are copied and node packages installed first, exactly as for
+    /// [`run_script`]. The output of the command is not captured or parsed,
+    /// it is passed through like the output of scripts.
+    ///
+    /// # Errors
+    ///
+    /// Fails under the same conditions as [`try_run_script`], returning
+    /// [`BuildError::CommandFailed`] if the command itself fails.
+    ///
+    /// [`target_directory`]: struct.Build.html#method.target_directory
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`BuildError::CommandFailed`]: enum.BuildError.html#variant.CommandFailed
+    pub fn npm_command(&mut self, subcommand: &str, args: &[&str]) -> Result<(), BuildError> {
+        if self.is_skipped(&self.node_env) {
+            return Ok(());
+        }
+        self.check_node_env(&self.node_env)?;
+        let npm = self.resolve_npm()?;
+        let envs = self.environment()?;
+        self.prepare(&npm, &envs)?;
+
+        let node_env = self.node_env.clone();
+        let mut command = self.command(&npm, &envs, &node_env);
+        command.arg(subcommand).args(args);
+        let npm_status = self.status(&mut command)?;
+        if npm_status.success() {
+            Ok(())
+        } else {
+            Err(BuildError::CommandFailed {
+                subcommand: subcommand.into(),
+                status: npm_status,
+            })
+        }
+    }
+
+    /// Run an npm script and return what it printed to stdout, trimmed,
+    /// e.g. for a script printing a version or a path the build needs.
+    ///
+    /// Packages are installed first, like for [`run_script`]. The script is
+    /// run with `--silent`, so npm does not print its own banner, and its
+    /// output is captured like with [`Command::output`]. Its stderr is
+    /// passed through once it finished. Outputs configured for other
+    /// scripts, e.g. with [`copy_outputs_to`], are not processed.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`], but returns
+    /// [`BuildError::ScriptOutputFailed`] including the captured stderr if
+    /// the script fails.
+    ///
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`Command::output`]: https://doc.rust-lang.org/std/process/struct.Command.html#method.output
+    /// [`copy_outputs_to`]: struct.Build.html#method.copy_outputs_to
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`BuildError::ScriptOutputFailed`]: enum.BuildError.html#variant.ScriptOutputFailed
+    pub fn run_script_output(&mut self, script_name: &str) -> Result<String, BuildError> {
+        let node_env = self.node_env.clone();
+        if self.is_skipped(&node_env) {
+            return Ok(String::new());
+        }
+        self.check_node_env(&node_env)?;
+        let npm = self.resolve_npm()?;
+        let envs = self.environment()?;
+        self.prepare(&npm, &envs)?;
+
+        let mut run = self.script_command(&npm, &envs, &node_env, script_name);
+        run.arg("--silent");
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = self.output(&mut run, true)?;
+        if !self.script_succeeded(status) {
+            return Err(BuildError::ScriptOutputFailed {
+                script: script_name.into(),
+                status,
+                stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            });
+        }
+        self.write_output(&Output {
+            status,
+            stdout: Vec::new(),
+            stderr,
+        })?;
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+    }
+
+    /// Run every script in the `package.json` of the [`project_directory`]
+    /// whose name starts with `prefix`, e.g. `build:`, in alphabetical
+    /// order. Node packages are installed once, before the first script.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`], stopping at the first script that
+    /// fails. Returns [`BuildError::NoMatchingScripts`] if no script
+    /// matches, which usually indicates a typo.
+    ///
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`BuildError::NoMatchingScripts`]: enum.BuildError.html#variant.NoMatchingScripts
+    pub fn run_scripts_matching(&mut self, prefix: &str) -> Result<&mut Self, BuildError> {
+        let manifest = self.manifest_path();
+        let matching: Vec<_> = scripts(&manifest)?
+            .into_iter()
+            .filter(|script| script.starts_with(prefix))
+            .collect();
+        if matching.is_empty() {
+            return Err(BuildError::NoMatchingScripts(prefix.into()));
+        }
+        for script in &matching {
+            self.try_run_script(script)?;
+        }
+        Ok(self)
+    }
+
+    /// Run the npm script `script_name` in every workspace of the project,
+    /// with each workspace after the workspaces it depends on, so packages
+    /// of a monorepo are built in dependency order.
+    ///
+    /// Workspaces are read from the `workspaces` field of the
+    /// `package.json` in the [`project_directory`], and their dependencies
+    /// from the `dependencies`, `devDependencies`, `optionalDependencies`
+    /// and `peerDependencies` of each workspace. The script is run with
+    /// `--workspace=<name> --if-present`, so workspaces without it are
+    /// skipped. Node packages are installed once, before the first
+    /// workspace. Nothing is run if the project has no workspaces.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`], stopping at the first workspace the
+    /// script fails in. Returns [`BuildError::DependencyCycle`] before
+    /// running anything if the workspaces depend on each other in a cycle.
+    ///
+    /// [`project_directory`]: struct.Build.html#method.project_directory
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`BuildError::DependencyCycle`]: enum.BuildError.html#variant.DependencyCycle
+    pub fn run_script_topo(&mut self, script_name: &str) -> Result<&mut Self, BuildError> {
+        let node_env = self.node_env.clone();
+        if self.is_skipped(&node_env) {
+            return Ok(self);
+        }
+        let workspaces = workspace_order(or_current_dir(&self.project_directory))?;
+        self.check_node_env(&node_env)?;
+        let npm = self.resolve_npm()?;
+        let envs = self.environment()?;
+        self.prepare(&npm, &envs)?;
+
+        for workspace in &workspaces {
+            let mut run = self.script_command(&npm, &envs, &node_env, script_name);
+            run.arg(format!("--workspace={}", workspace))
+                .arg("--if-present");
+            let npm_status = self.status(&mut run)?;
+            if !self.script_succeeded(npm_status) {
+                return Err(BuildError::ScriptFailed {
+                    script: format!("{} in workspace {}", script_name, workspace),
+                    status: npm_status,
+                });
+            }
+        }
+        self.finish_script()?;
+        self.remove_source()?;
+        Ok(self)
+    }
+
+    /// Run several independent npm scripts at the same time, after
+    /// installing node packages once.
+    ///
+    /// The output of each script is captured and written out in order once
+    /// all scripts have finished, so it does not interleave. Scripts that
+    /// write to the same files race with each other and should be run with
+    /// [`run_script`] instead.
+    ///
+    /// Once all scripts succeeded their outputs are processed and the
+    /// source is removed as after [`run_script`]. If any fails, the
+    /// [`on_failure_script`] runs once and the whole set is run again if
+    /// [`retry`] allows it.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`try_run_script`] if installing fails. Otherwise all
+    /// scripts are run to completion and [`BuildError::ScriptsFailed`]
+    /// collects the failures of every script that did not succeed.
+    ///
+    /// [`run_script`]: struct.Build.html#method.run_script
+    /// [`on_failure_script`]: struct.Build.html#method.on_failure_script
+    /// [`retry`]: struct.Build.html#method.retry
+    /// [`try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`BuildError::ScriptsFailed`]: enum.BuildError.html#variant.ScriptsFailed
+    pub fn run_scripts_parallel(&mut self, scripts: &[&str]) -> Result<(), BuildError> {
+        let node_env = self.node_env.clone();
+        if self.is_skipped(&node_env) {
+            return Ok(());
+        }
+        self.retrying(|build| {
+            build.check_node_env(&node_env)?;
+            let npm = build.resolve_npm()?;
+            let envs = build.environment()?;
+            let result = build.run_prepared_parallel(&npm, &envs, scripts, &node_env);
+            if result.is_err() {
+                build.run_failure_script(&npm, &envs, &node_env);
+            }
+            result
+        })
+    }
+
+    fn run_prepared_parallel(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+        scripts: &[&str],
+        node_env: &NodeEnv,
+    ) -> Result<(), BuildError> {
+        self.prepare(npm, envs)?;
+
+        let mut errors = Vec::new();
+        let mut running = Vec::new();
+        for script in scripts {
+            let mut command = self.script_command(npm, envs, node_env, script);
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            self.apply_hooks(&mut command);
+            let mut wrapped = self.shell.as_ref().map(|shell| {
+                let mut wrapped = through_shell(shell, &command, self.env_allowlist.is_some());
+                wrapped.stdout(Stdio::piped()).stderr(Stdio::piped());
+                wrapped
+            });
+            let spawned = wrapped.as_mut().unwrap_or(&mut command);
+            self.apply_limits(spawned);
+            let start = Instant::now();
+            match self.spawn(spawned) {
+                Ok((child, tracked)) => {
+                    let waiting = thread::spawn(move || {
+                        let output = child.wait_with_output();
+                        drop(tracked);
+                        output
+                    });
+                    running.push((script, command, start, waiting));
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+
+        for (script, command, start, waiting) in running {
+            let output = waiting
+                .join()
+                .expect("Thread waiting for npm panicked")
+                .map_err(BuildError::SpawnFailed);
+            let output = match output {
+                Ok(output) => output,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            self.record(&command, start, output.status);
+            self.write_output(&output)?;
+            if !self.script_succeeded(output.status) {
+                errors.push(BuildError::ScriptFailed {
+                    script: script.to_string(),
+                    status: output.status,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            self.finish_script()?;
+            self.remove_source()
+        } else {
+            Err(BuildError::ScriptsFailed(errors))
+        }
+    }
+
+    /// Runs the script `script_name` with `args`, running the failure
+    /// script if anything fails. If `tolerate_failure` is set a non 0 exit
+    /// code of the script itself does not run the failure script.
+    fn run_script_checked(
+        &mut self,
+        script_name: &str,
+        args: &[String],
+        node_env: &NodeEnv,
+        tolerate_failure: bool,
+    ) -> Result<(), BuildError> {
+        if self.is_skipped(node_env) {
+            return Ok(());
+        }
+        self.check_node_env(node_env)?;
+        let npm = self.resolve_npm()?;
+        let envs = self.environment()?;
+        let result = self.run_prepared_script(&npm, &envs, script_name, args, node_env);
+        match &result {
+            Err(BuildError::ScriptFailed { .. }) if tolerate_failure => {}
+            Err(_) => self.run_failure_script(&npm, &envs, node_env),
+            Ok(()) => {}
+        }
+        result
+    }
+
+    fn run_prepared_script(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+        script_name: &str,
+        args: &[String],
+        node_env: &NodeEnv,
+    ) -> Result<(), BuildError> {
+        self.prepare(npm, envs)?;
+
+        let mut run = self.script_command(npm, envs, node_env, script_name);
+        if !args.is_empty() {
+            run.arg("--").args(args);
+        }
+        let npm_status = self.status(&mut run)?;
+        if self.script_succeeded(npm_status) {
+            self.finish_script()?;
+            self.remove_source()
+        } else {
+            Err(BuildError::ScriptFailed {
+                script: script_name.into(),
+                status: npm_status,
+            })
+        }
+    }
+
+    /// Runs the opt-in checks of the environment before anything is
+    /// copied or installed.
+    fn preflight(&self) -> Result<(), BuildError> {
+        if self.require_node {
+            let envs = self.environment()?;
+            let found = match &self.shell {
+                Some(_) => matches!(
+                    self.node_version_command(&envs).output(),
+                    Ok(output) if output.status.success()
+                ),
+                None => self.find_node(&envs).is_some(),
+            };
+            if !found {
+                return Err(BuildError::NodeNotFound);
+            }
+        }
+        if let Some(expected) = &self.module_type {
+            let manifest = self.manifest_path();
+            let actual = read_manifest(&manifest)?
+                .get("type")
+                .and_then(|ty| ty.as_str())
+                .unwrap_or("commonjs")
+                .to_string();
+            if actual != *expected {
+                return Err(BuildError::ModuleTypeMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        if self.check_node_version {
+            let project = or_current_dir(&self.project_directory);
+            if let Some(expected) = pinned_node_version(project)? {
+                if expected.starts_with(|c: char| c.is_ascii_digit() || c == 'v') {
+                    let output = self
+                        .node_version_command(&self.environment()?)
+                        .output()
+                        .map_err(BuildError::NodeVersionUnavailable)?;
+                    let actual = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                    if !node_version_matches(&expected, &actual) {
+                        return Err(BuildError::NodeVersionMismatch { expected, actual });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up `node` in the `PATH` npm is run with, given the variables
+    /// `envs` set for it.
+    fn find_node(&self, envs: &[(OsString, OsString)]) -> Option<PathBuf> {
+        let inherits_path = match &self.env_allowlist {
+            Some(keys) => keys.iter().any(|key| key == "PATH"),
+            None => true,
+        };
+        let path = envs
+            .iter()
+            .rev()
+            .find(|(key, _)| key == "PATH")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env::var_os("PATH").filter(|_| inherits_path))?;
+        which::which_in("node", Some(path), or_current_dir(&self.project_directory)).ok()
+    }
+
+    /// The command running `node --version` in the project directory with
+    /// the environment and shell npm is run with.
+    fn node_version_command(&self, envs: &[(OsString, OsString)]) -> Command {
+        let node = match &self.shell {
+            Some(_) => None,
+            None => self.find_node(envs),
+        };
+        let mut command = self.command(
+            node.as_deref().unwrap_or_else(|| Path::new("node")),
+            envs,
+            &self.node_env,
+        );
+        command
+            .arg("--version")
+            .current_dir(or_current_dir(&self.project_directory));
+        match &self.shell {
+            Some(shell) => through_shell(shell, &command, self.env_allowlist.is_some()),
+            None => command,
+        }
+    }
+
+    /// Runs `npm outdated` and fails if it lists any package.
+    fn check_outdated(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+    ) -> Result<(), BuildError> {
+        let mut outdated = self.command(npm, envs, &NodeEnv::Development);
+        outdated.args(["outdated", "--json"]);
+        let output = self.output(&mut outdated, false)?;
+        // npm outdated exits with 1 if it found outdated packages, so only
+        // output that cannot be parsed indicates a failure
+        match serde_json::from_slice::<serde_json::Value>(&output.stdout) {
+            Ok(serde_json::Value::Object(packages)) if packages.is_empty() => Ok(()),
+            Ok(serde_json::Value::Object(packages)) => Err(BuildError::OutdatedDependencies(
+                packages.keys().cloned().collect(),
+            )),
+            _ if output.stdout.iter().all(u8::is_ascii_whitespace) && output.status.success() => {
+                Ok(())
+            }
+            _ => Err(BuildError::CommandFailed {
+                subcommand: "outdated".into(),
+                status: output.status,
+            }),
+        }
+    }
+
+    fn check_npm_version(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+    ) -> Result<(), BuildError> {
+        let required = match &self.npm_version {
+            Some(required) => required.clone(),
+            None => return Ok(()),
+        };
+        let requirement = semver::VersionReq::parse(&required).map_err(|source| {
+            BuildError::InvalidVersionRequirement {
+                requirement: required.clone(),
+                source,
+            }
+        })?;
+        let mut version = self.command(npm, envs, &NodeEnv::Development);
+        version.arg("--version");
+        let output = self.output(&mut version, false)?;
+        if !output.status.success() {
+            return Err(BuildError::CommandFailed {
+                subcommand: "--version".into(),
+                status: output.status,
+            });
+        }
+        let actual = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        match semver::Version::parse(actual.trim_start_matches('v')) {
+            Ok(version) if requirement.matches(&version) => Ok(()),
+            _ => Err(BuildError::NpmVersionTooOld { required, actual }),
+        }
+    }
+
+    /// Emits or writes the locked packages, see [`emit_dependency_list`].
+    ///
+    /// [`emit_dependency_list`]: struct.Build.html#method.emit_dependency_list
+    fn emit_dependencies(&self) -> Result<(), BuildError> {
+        let packages = locked_packages(self.effective_target())?;
+        match &self.dependency_list {
+            Some(path) => {
+                let contents: String = packages.iter().map(|p| format!("{}\n", p)).collect();
+                fs::write(path, contents).map_err(|source| BuildError::CannotWriteManifest {
+                    path: path.clone(),
+                    source,
+                })
+            }
+            None => {
+                for package in packages {
+                    println!("cargo:warning={}", package);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_install(
+        &mut self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+    ) -> Result<(), BuildError> {
+        let mut ls = self.command(npm, envs, &NodeEnv::Development);
+        ls.args(["ls", "--depth=0"]);
+        let output = self.output(&mut ls, true)?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let problems = if stderr.trim().is_empty() {
+            String::from_utf8_lossy(&output.stdout)
+        } else {
+            stderr
+        };
+        Err(BuildError::BrokenInstall(problems.trim().to_string()))
+    }
+
+    /// Runs the preflight checks, creates the target directory, copies the
+    /// selected items and applies the manifest patches.
+    fn prepare_files(&mut self) -> Result<(), BuildError> {
+        self.preflight()?;
+        let outside = self.injected_files.iter().find(|(path, _)| {
+            path.is_absolute()
+                || path
+                    .components()
+                    .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        });
+        if let Some((path, _)) = outside {
+            return Err(BuildError::InjectedFileOutsideTarget(path.clone()));
+        }
+        if self.temp_outputs.is_some() && self.temp_target.is_none() {
+            let temp = TempDir::new().map_err(|source| BuildError::CannotCreateTarget {
+                path: env::temp_dir(),
+                source,
+            })?;
+            self.temp_target = Some(temp);
+        }
+        create_dir_all(self.effective_target()).map_err(|source| {
+            BuildError::CannotCreateTarget {
+                path: self.target_directory.clone(),
+                source,
+            }
+        })?;
+        let target_empty = get_folder_contents(self.effective_target(), false)?.is_empty();
+        let project = or_current_dir(&self.project_directory);
+        let changed = match &self.changed_since {
+            Some(git_ref) if self.copies_items() && !target_empty => {
+                changed_files(project, git_ref)
+            }
+            _ => None,
+        };
+        if let Some(changed) = changed {
+            self.copied = copy_changed(&changed, project, self.effective_target())?;
+        } else if self.copies_items() && (target_empty || !self.copy_once) {
+            let copy = self.resolved_copy()?;
+            let project = or_current_dir(&self.project_directory);
+            self.copied = copy_to_target(
+                &copy,
+                project,
+                self.effective_target(),
+                self.parallel_copy,
+                self.overwrite_readonly,
+            )?;
+            if self.verify_copy {
+                let items = match copy {
+                    CopyItems::All => get_folder_contents(project, false)?,
+                    CopyItems::Some(items) | CopyItems::Sync(items) => items,
+                    CopyItems::Nothing => Vec::new(),
+                };
+                let missing = missing_copies(&items, project, self.effective_target())?;
+                if !missing.is_empty() {
+                    return Err(BuildError::CopyIncomplete(missing));
+                }
+            }
+        }
+        let path = self.effective_target().join("package.json");
+        if !self.manifest_patches.is_empty() {
+            let mut manifest = match self.patched_manifest.take() {
+                Some(manifest) => manifest,
+                None => read_manifest(&path)?,
+            };
+            for patch in self.manifest_patches.drain(..) {
+                (patch.0)(&mut manifest);
+            }
+            self.patched_manifest = Some(manifest);
+        }
+        if let Some(manifest) = &self.patched_manifest {
+            write_manifest(&path, manifest)?;
+        }
+        for (path, contents) in &self.injected_files {
+            let path = self.effective_target().join(path);
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)?;
+            }
+            fs::write(&path, contents)?;
+        }
+        self.files_prepared = true;
+        Ok(())
+    }
+
+    /// Builds the command installing packages with `args`.
+    fn install_command(
+        &self,
+        npm: &Path,
+        envs: &[(OsString, OsString)],
+        args: &[String],
+    ) -> Result<Command, BuildError> {
+        let mut install = self.command(npm, envs, &NodeEnv::Development);
+        install.args(args);
+        for (registry, token) in &self.auth_tokens {
+            install.env(format!("npm_config_{}:_authToken", registry), &token.0);
+        }
+        if !self.strict_ssl {
+            println!(
+                "cargo:warning=npm-rs: installing packages without verifying TLS certificates"
+            );
+            install.env("npm_config_strict_ssl", "false");
+        }
+        if let Some(dir) = &self.tmp_dir {
+            let dir = absolute_path(dir);
+            create_dir_all(&dir)?;
+            let vars: &[&str] = if cfg!(windows) {
+                &["npm_config_tmp", "TEMP", "TMP"]
+            } else {
+                &["npm_config_tmp", "TMPDIR"]
+            };
+            for var in vars {
+                install.env(var, &dir);
+            }
+        }
+        Ok(install)
+    }
+
+    /// Takes the lock of [`serialize_installs`] if enabled. The lock is
+    /// released when the returned file is dropped, also when returning
+    /// early or unwinding.
+    ///
+    /// [`serialize_installs`]: struct.Build.html#method.serialize_installs
+    fn lock_installs(&self) -> Result<Option<File>, BuildError> {
+        if !self.serialize_installs {
+            return Ok(None);
+        }
+        let target = absolute_path(or_current_dir(&self.target_directory));
+        let path = match (target.parent(), target.file_name()) {
+            (Some(parent), Some(name)) => {
+                create_dir_all(parent)?;
+                parent.join(format!(".{}.npm-rs.lock", name.to_string_lossy()))
+            }
+            _ => target.join(".npm-rs.lock"),
+        };
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        lock.lock_exclusive()?;
+        Ok(Some(lock))
+    }
+
+    /// Copies files and installs node packages unless this was already
+    /// done for the current directories.
+    fn prepare(&mut self, npm: &Path, envs: &[(OsString, OsString)]) -> Result<(), BuildError> {
+        self.did_install = false;
+        if !self.installed || !self.files_prepared {
+            let _lock = self.lock_installs()?;
+            if !self.files_prepared {
+                self.prepare_files()?;
+                // Syncing the copied items removes `node_modules` unless it
+                // is one of them.
+                self.installed &= self.effective_target().join("node_modules").is_dir();
+            }
+            if self.installed {
+                return Ok(());
+            }
+            self.check_npm_version(npm, envs)?;
+
+            let in_sync = self.fast_sync && node_modules_in_sync(self.effective_target());
+            if !self.install_disabled && !self.install_skipped && !in_sync {
+                let target = self.effective_target();
+                if self.verify_lockfile && target.join("package-lock.json").is_file() {
+                    let drift = lockfile_drift(target)?;
+                    if !drift.is_empty() {
+                        return Err(BuildError::LockfileOutOfSync(drift));
+                    }
+                }
+
+                let args = self.install_args();
+                if args[0] == "ci" && self.require_lockfile == Some(true) && !self.has_lockfile() {
+                    return Err(BuildError::MissingLockfile(self.effective_target().into()));
+                }
+                if let Some(required) = self.required_free_space {
+                    let available = fs2::available_space(self.effective_target())?;
+                    if available < required {
+                        return Err(BuildError::InsufficientDiskSpace {
+                            available,
+                            required,
+                        });
+                    }
+                }
+                let mut install = self.install_command(npm, envs, &args)?;
+                let npm_status = if self.ci_fallback && args[0] == "ci" {
+                    let output = self.output(&mut install, true)?;
+                    self.replay_output(&output)?;
+                    if !output.status.success() && ci_lockfile_error(&output) {
+                        println!("cargo:warning=npm-rs: npm ci failed because of the lockfile, running npm install instead");
+                        let mut args = args;
+                        args[0] = "install".into();
+                        let mut install = self.install_command(npm, envs, &args)?;
+                        self.status(&mut install)?
+                    } else {
+                        output.status
+                    }
+                } else {
+                    self.status(&mut install)?
+                };
+                if !npm_status.success() {
+                    return Err(BuildError::InstallFailed(npm_status));
+                }
+                self.did_install = true;
+
+                if let Some(limit) = self.max_node_modules_size {
+                    let node_modules = self.effective_target().join("node_modules");
+                    if node_modules.is_dir() {
+                        let actual = dir_size(&node_modules)?;
+                        if actual > limit {
+                            return Err(BuildError::NodeModulesTooLarge { actual, limit });
+                        }
+                    }
+                }
+
+                if self.dedupe {
+                    let mut dedupe = self.command(npm, envs, &NodeEnv::Development);
+                    dedupe.arg("dedupe");
+                    let npm_status = self.status(&mut dedupe)?;
+                    if !npm_status.success() {
+                        return Err(BuildError::CommandFailed {
+                            subcommand: "dedupe".into(),
+                            status: npm_status,
+                        });
+                    }
+                }
+
+                for (program, args) in self.additional_installs.clone() {
+                    let mut install =
+                        self.command(Path::new(&program), envs, &NodeEnv::Development);
+                    install.args(&args);
+                    let status = self.status(&mut install)?;
+                    if !status.success() {
+                        return Err(BuildError::AdditionalInstallFailed {
+                            command: program,
+                            status,
+                        });
+                    }
+                }
+            }
+
+            if self.fail_if_outdated {
+                self.check_outdated(npm, envs)?;
+            }
+            if self.verify_install {
+                self.check_install(npm, envs)?;
+            }
+            if self.emit_dependencies {
+                self.emit_dependencies()?;
+            }
+
+            self.installed = true;
+            self.install_skipped = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a project with an empty `package.json` in a temporary
+    /// directory.
+    fn project() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("package.json"), "{}").unwrap();
+        dir
+    }
+
+    /// Writes an executable shell script `name` running `body` to `dir`.
+    #[cfg(unix)]
+    fn fake_program(dir: &Path, name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let program = dir.join(name);
+        fs::write(&program, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        program
+    }
+
+    /// Writes an npm stand-in to `dir` that succeeds without doing
+    /// anything.
+    #[cfg(unix)]
+    fn fake_npm(dir: &Path) -> PathBuf {
+        fake_program(dir, "npm", "exit 0")
+    }
+
+    /// The number of times packages were installed by `build`.
+    fn installs(build: &Build) -> usize {
+        build
+            .report()
+            .commands
+            .iter()
+            .filter(|command| command.args[0] == "install" || command.args[0] == "ci")
+            .count()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skip_install_is_kept_when_the_directory_changes_afterwards() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let mut build = Build::new();
+        build
+            .npm_path(fake_npm(bin.path()))
+            .skip_install()
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .try_run_script("build")
+            .unwrap();
+        assert_eq!(installs(&build), 0);
+
+        build.try_run_script("build").unwrap();
+        assert_eq!(installs(&build), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skip_install_applies_after_a_directory_change() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let mut build = Build::new();
+        build
+            .npm_path(fake_npm(bin.path()))
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .try_run_script("build")
+            .unwrap();
+        assert_eq!(installs(&build), 1);
+
+        build
+            .target_directory(project.path())
+            .skip_install()
+            .try_run_script("build")
+            .unwrap();
+        assert_eq!(installs(&build), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skip_install_only_skips_the_next_install() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let mut build = Build::new();
+        build
+            .npm_path(fake_npm(bin.path()))
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .skip_install()
+            .try_run_script("build")
+            .unwrap();
+        assert_eq!(installs(&build), 0);
+
+        build
+            .target_directory(project.path())
+            .try_run_script("build")
+            .unwrap();
+        assert_eq!(installs(&build), 1);
+    }
+
+    #[test]
+    fn max_sockets_is_passed_to_install() {
+        let mut build = Build::new();
+        assert!(!build
+            .install_args()
+            .iter()
+            .any(|arg| arg.starts_with("--maxsockets")));
+
+        build.max_sockets(8);
+        assert!(build.install_args().contains(&"--maxsockets=8".to_string()));
+    }
+
+    static LOOKUPS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    fn counting_lookup(name: &str) -> Option<PathBuf> {
+        LOOKUPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Some(PathBuf::from("/stub").join(name))
+    }
+
+    #[test]
+    fn npm_is_looked_up_once_and_npm_path_overrides_it() {
+        use std::sync::atomic::Ordering;
+
+        let mut build = Build::new();
+        build.lookup = counting_lookup;
+        assert_eq!(build.resolve_npm().unwrap(), Path::new("/stub/npm"));
+        assert_eq!(build.resolve_npm().unwrap(), Path::new("/stub/npm"));
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 1);
+
+        build.npm_path("/custom/npm");
+        assert_eq!(build.resolve_npm().unwrap(), Path::new("/custom/npm"));
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn uncreatable_target_is_reported() {
+        let project = project();
+        let file = project.path().join("package.json");
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(file.join("target"));
+        match build.prepare_only() {
+            Err(BuildError::CannotCreateTarget { path, .. }) => {
+                assert_eq!(path, file.join("target"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn overwrite_readonly_replaces_read_only_files() {
+        let (project, target) = (project(), TempDir::new().unwrap());
+        fs::write(project.path().join("data.txt"), "new").unwrap();
+        let existing = target.path().join("data.txt");
+        fs::write(&existing, "old").unwrap();
+        let mut permissions = fs::metadata(&existing).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&existing, permissions).unwrap();
+
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(target.path())
+            .copy_items(["data.txt"])
+            .overwrite_readonly(true)
+            .prepare_only()
+            .unwrap();
+        assert_eq!(fs::read_to_string(existing).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn overwrite_readonly_replaces_read_only_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (project, target) = (project(), TempDir::new().unwrap());
+        fs::create_dir(project.path().join("data")).unwrap();
+        fs::write(project.path().join("data").join("file.txt"), "new").unwrap();
+        let existing = target.path().join("data");
+        fs::create_dir(&existing).unwrap();
+        fs::write(existing.join("file.txt"), "old").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(target.path())
+            .copy_items(["data"])
+            .overwrite_readonly(true)
+            .prepare_only()
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(existing.join("file.txt")).unwrap(),
+            "new"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn require_node_searches_the_path_npm_is_run_with() {
+        let (bin, empty, project) = (TempDir::new().unwrap(), TempDir::new().unwrap(), project());
+        fake_program(bin.path(), "node", "echo v18.0.0");
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .env("PATH", empty.path())
+            .require_node();
+        match build.prepare_only() {
+            Err(BuildError::NodeNotFound) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        build.path_prepend(bin.path()).prepare_only().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn node_version_is_checked_with_the_configured_path() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        fake_program(bin.path(), "node", "echo v18.17.0");
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .env("PATH", bin.path())
+            .check_node_version_file();
+        fs::write(project.path().join(".nvmrc"), "18\n").unwrap();
+        build.prepare_only().unwrap();
+
+        fs::write(project.path().join(".nvmrc"), "20\n").unwrap();
+        match build.prepare_only() {
+            Err(BuildError::NodeVersionMismatch { actual, .. }) => assert_eq!(actual, "v18.17.0"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allowed_failures_do_not_run_the_failure_script() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let npm = fake_program(bin.path(), "npm", "[ \"$2\" != lint ]");
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .never_install()
+            .on_failure_script("cleanup")
+            .run_script_allow_failure("lint");
+        assert_eq!(build.failed_scripts().len(), 1);
+        assert!(!build
+            .report()
+            .commands
+            .iter()
+            .any(|command| command.args.contains(&"cleanup".to_string())));
+
+        assert!(build.try_run_script("lint").is_err());
+        assert!(build
+            .report()
+            .commands
+            .iter()
+            .any(|command| command.args.contains(&"cleanup".to_string())));
+    }
+
+    #[test]
+    fn missing_optional_packages_keep_node_modules_in_sync() {
+        let project = project();
+        fs::write(
+            project.path().join("package-lock.json"),
+            r#"{"packages": {
+                "": {"name": "app"},
+                "node_modules/esbuild": {"version": "0.19.0"},
+                "node_modules/@esbuild/linux-x64": {"version": "0.19.0", "optional": true},
+                "node_modules/@esbuild/win32-x64": {"version": "0.19.0", "optional": true}
+            }}"#,
+        )
+        .unwrap();
+        fs::create_dir(project.path().join("node_modules")).unwrap();
+        let hidden = project
+            .path()
+            .join("node_modules")
+            .join(".package-lock.json");
+        fs::write(
+            &hidden,
+            r#"{"packages": {
+                "node_modules/esbuild": {"version": "0.19.0"},
+                "node_modules/@esbuild/linux-x64": {"version": "0.19.0", "optional": true}
+            }}"#,
+        )
+        .unwrap();
+        assert!(node_modules_in_sync(project.path()));
+
+        fs::write(
+            &hidden,
+            r#"{"packages": {
+                "node_modules/@esbuild/linux-x64": {"version": "0.19.0", "optional": true}
+            }}"#,
+        )
+        .unwrap();
+        assert!(!node_modules_in_sync(project.path()));
+    }
+
+    #[test]
+    fn parallel_copy_reports_every_failed_item() {
+        let (project, target) = (project(), TempDir::new().unwrap());
+        let items = CopyItems::Some(vec![
+            "package.json".into(),
+            "missing-a".into(),
+            "missing-b".into(),
+        ]);
+        match copy_to_target(&items, project.path(), target.path(), true, false) {
+            Err(BuildError::CopyFailed(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(target.path().join("package.json").is_file());
+    }
+
+    #[test]
+    fn injected_files_stay_inside_the_target() {
+        let project = project();
+        for path in &["../escaped.txt", "nested/../../escaped.txt"] {
+            let mut build = Build::new();
+            build
+                .project_directory(project.path())
+                .target_directory(project.path())
+                .inject_file(path, "x");
+            match build.prepare_only() {
+                Err(BuildError::InjectedFileOutsideTarget(rejected)) => {
+                    assert_eq!(rejected, Path::new(path))
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .inject_file(project.path().join("absolute.txt"), "x");
+        assert!(build.prepare_only().is_err());
+        assert!(!project.path().join("absolute.txt").exists());
+    }
+
+    #[cfg(all(unix, feature = "serde"))]
+    #[test]
+    fn report_json_accepts_non_utf8_paths() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut build = Build::new();
+        build.npm_path(OsStr::from_bytes(b"/opt/n\xffpm"));
+        let report: serde_json::Value = serde_json::from_str(&build.report_json()).unwrap();
+        assert_eq!(report["npm_path"], "/opt/n\u{fffd}pm");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_keeps_the_default_signal_handlers() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let mut build = Build::new();
+        build
+            .npm_path(fake_npm(bin.path()))
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .never_install()
+            .timeout(Duration::from_secs(60))
+            .try_run_script("build")
+            .unwrap();
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { libc::sigaction(signal, std::ptr::null(), &mut action) },
+                0
+            );
+            assert_eq!(action.sa_sigaction, libc::SIG_DFL);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serialized_installs_do_not_overlap() {
+        let (bin, root) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let project = root.path().join("app");
+        fs::create_dir(&project).unwrap();
+        fs::write(project.join("package.json"), "{}").unwrap();
+        let npm = fake_program(
+            bin.path(),
+            "npm",
+            "[ \"$1\" = install ] && echo start >> \"$INSTALL_LOG\" && sleep 0.3 && echo end >> \"$INSTALL_LOG\"\nexit 0",
+        );
+        let log = root.path().join("installs.log");
+        let builds: Vec<_> = (0..2)
+            .map(|_| {
+                let (npm, dir, log) = (npm.clone(), project.clone(), log.clone());
+                thread::spawn(move || {
+                    Build::new()
+                        .npm_path(npm)
+                        .project_directory(&dir)
+                        .target_directory(&dir)
+                        .env("INSTALL_LOG", log)
+                        .serialize_installs(true)
+                        .try_run_script("build")
+                        .map(|_| ())
+                })
+            })
+            .collect();
+        for build in builds {
+            build.join().unwrap().unwrap();
+        }
+        assert_eq!(fs::read_to_string(log).unwrap(), "start\nend\nstart\nend\n");
+        assert!(root.path().join(".app.npm-rs.lock").is_file());
+        assert!(!project.join(".npm-rs.lock").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_hash_covers_only_declared_outputs() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let npm = fake_program(
+            bin.path(),
+            "npm",
+            "mkdir -p dist && echo bundle > dist/main.js",
+        );
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .never_install()
+            .assert_output_hash("00");
+        match build.try_run_script("build") {
+            Err(BuildError::NoOutputsToHash) => {}
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+
+        build.expect_outputs_glob(&["dist/*.js"]);
+        let mut hash = || match build.try_run_script("build") {
+            Err(BuildError::OutputHashMismatch { actual, .. }) => actual,
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        };
+        let before = hash();
+        fs::write(
+            project.path().join("package.json"),
+            r#"{"name": "changed"}"#,
+        )
+        .unwrap();
+        assert_eq!(hash(), before);
+    }
+
+    #[cfg(all(unix, feature = "serde"))]
+    #[test]
+    fn report_json_lists_the_outputs() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let project = project();
+        let mut build = Build::new();
+        build
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .copy_outputs_to(
+                &[
+                    Path::new("dist"),
+                    Path::new(OsStr::from_bytes(b"n\xffme.js")),
+                ],
+                project.path().join("out"),
+            );
+        let report = build.report();
+        assert_eq!(report.outputs.len(), 2);
+        let json: serde_json::Value = serde_json::from_str(&build.report_json()).unwrap();
+        assert_eq!(
+            json["outputs"],
+            serde_json::json!(["dist", "n\u{fffd}me.js"])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ci_falls_back_only_on_lockfile_sync_errors() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        fs::write(project.path().join("package-lock.json"), "{}").unwrap();
+        let out_of_sync = "npm error `npm ci` can only install packages when your package.json and package-lock.json or npm-shrinkwrap.json are in sync.";
+        for (message, falls_back) in [(out_of_sync, true), ("npm error code EUSAGE", false)] {
+            let npm = fake_program(
+                bin.path(),
+                "npm",
+                &format!("[ \"$1\" != ci ] || {{ echo '{}' >&2; exit 1; }}", message),
+            );
+            let log = project.path().join("npm.log");
+            let mut build = Build::new();
+            build
+                .npm_path(npm)
+                .project_directory(project.path())
+                .target_directory(project.path())
+                .fast_sync()
+                .ci_fallback_to_install(true)
+                .log_to(&log, &log);
+            assert_eq!(build.try_run_script("build").is_ok(), falls_back);
+            assert!(fs::read_to_string(&log).unwrap().contains(message));
+            let commands: Vec<_> = build
+                .report()
+                .commands
+                .iter()
+                .map(|command| command.args[0].clone())
+                .collect();
+            assert_eq!(commands.contains(&"install".to_string()), falls_back);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parallel_scripts_clean_up_and_retry_like_other_scripts() {
+        let (bin, project, target) = (TempDir::new().unwrap(), project(), TempDir::new().unwrap());
+        fs::create_dir(project.path().join("src")).unwrap();
+        let runs = |build: &Build, script: &str| {
+            build
+                .report()
+                .commands
+                .iter()
+                .filter(|command| command.args.contains(&script.to_string()))
+                .count()
+        };
+
+        let npm = fake_program(bin.path(), "npm", "[ \"$2\" != b ]");
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(target.path())
+            .copy_items(["src"])
+            .never_install()
+            .on_failure_script("cleanup")
+            .retry(1);
+        assert!(build.run_scripts_parallel(&["a", "b"]).is_err());
+        assert_eq!((runs(&build, "b"), runs(&build, "cleanup")), (2, 2));
+
+        let npm = fake_npm(bin.path());
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(target.path())
+            .copy_items(["src"])
+            .never_install()
+            .remove_source_after(&[]);
+        build.run_scripts_parallel(&["a", "b"]).unwrap();
+        assert!(!target.path().join("src").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn script_variants_execute_like_planned_scripts() {
+        let (bin, project) = (TempDir::new().unwrap(), project());
+        let marker = project.path().join("failed-once");
+        let npm = fake_program(
+            bin.path(),
+            "npm",
+            &format!(
+                "[ -e {0} ] || {{ touch {0}; exit 1; }}\necho \"$*\" >> {1}",
+                marker.display(),
+                project.path().join("npm.log").display()
+            ),
+        );
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(project.path())
+            .never_install()
+            .retry(1);
+        build
+            .run_script_cmdline("build", "--mode \"staging eu\"")
+            .unwrap();
+        assert_eq!(build.report().attempts, 2);
+        let log = fs::read_to_string(project.path().join("npm.log")).unwrap();
+        assert!(log.contains("build -- --mode staging eu"), "{}", log);
+
+        build.run_script_for_envs("build", &["test"]);
+        assert_eq!(build.report().attempts, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removing_the_source_keeps_the_installed_packages() {
+        let (bin, project, target) = (TempDir::new().unwrap(), project(), TempDir::new().unwrap());
+        fs::create_dir(project.path().join("src")).unwrap();
+        let npm = fake_program(
+            bin.path(),
+            "npm",
+            "case \"$1\" in install) mkdir -p node_modules ;; run) [ -d src ] ;; esac",
+        );
+        let mut build = Build::new();
+        build
+            .npm_path(npm)
+            .project_directory(project.path())
+            .target_directory(target.path())
+            .copy_items(["src"])
+            .remove_source_after(&[]);
+        build.try_run_script("build").unwrap();
+        assert!(!target.path().join("src").exists());
+
+        build.try_run_script("build").unwrap();
+        assert_eq!(installs(&build), 1);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn config_rejects_copy_all_with_copy_items() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("npm.toml");
+        fs::write(&path, "copy_all = true\ncopy_items = [\"src\"]\n").unwrap();
+        match Build::from_config_file(&path) {
+            Err(err @ BuildError::InvalidConfigFile { .. }) => {
+                assert!(
+                    err.to_string().contains("copy_all and copy_items"),
+                    "{}",
+                    err
+                )
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn debug_output_redacts_environment_values() {
+        let mut build = Build::new();
+        build.env("API_KEY", "hunter2");
+        let debug = format!("{:?}", build);
+        assert!(debug.contains("API_KEY"), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
     }
 }
@@ -0,0 +1,76 @@
+use std::process::Command;
+use std::time::Duration;
+
+/// Limits on the resources npm and its child processes may use
+///
+/// Passed to [`Build::resource_limits`]. Fields left at `None` are not
+/// limited.
+///
+/// [`Build::resource_limits`]: struct.Build.html#method.resource_limits
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// The maximum size of the virtual memory of each process in bytes,
+    /// `RLIMIT_AS`
+    pub max_address_space: Option<u64>,
+    /// The maximum CPU time of each process, `RLIMIT_CPU`. Rounded up to
+    /// whole seconds.
+    pub max_cpu_time: Option<Duration>,
+}
+
+#[cfg(unix)]
+fn set_limit(resource: libc::c_int, limit: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: limit as libc::rlim_t,
+        rlim_max: limit as libc::rlim_t,
+    };
+    // The type of the resource argument differs between libcs
+    if unsafe { libc::setrlimit(resource as _, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Makes `command` apply `limits` to the process it starts.
+#[cfg(unix)]
+pub(crate) fn apply(command: &mut Command, limits: ResourceLimits) {
+    use std::os::unix::process::CommandExt;
+
+    let cpu_seconds = limits
+        .max_cpu_time
+        .map(|time| time.as_secs() + u64::from(time.subsec_nanos() > 0));
+    // Safety: the closure only calls setrlimit, which is async-signal-safe,
+    // and does not allocate
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(bytes) = limits.max_address_space {
+                set_limit(libc::RLIMIT_AS as _, bytes)?;
+            }
+            if let Some(seconds) = cpu_seconds {
+                set_limit(libc::RLIMIT_CPU as _, seconds)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn apply(_command: &mut Command, _limits: ResourceLimits) {}
+
+/// Makes `command` set the file mode creation mask of the process it
+/// starts to `mask`.
+#[cfg(unix)]
+pub(crate) fn apply_umask(command: &mut Command, mask: u32) {
+    use std::os::unix::process::CommandExt;
+
+    // Safety: umask is async-signal-safe and cannot fail
+    unsafe {
+        command.pre_exec(move || {
+            libc::umask(mask as libc::mode_t);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn apply_umask(_command: &mut Command, _mask: u32) {}
@@ -0,0 +1,301 @@
+use crate::BuildError;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Reads and parses the `package.json` at `manifest`.
+pub(crate) fn read_manifest(manifest: &Path) -> Result<Value, BuildError> {
+    let contents =
+        fs::read_to_string(manifest).map_err(|source| BuildError::CannotReadManifest {
+            path: manifest.into(),
+            source,
+        })?;
+    serde_json::from_str(&contents).map_err(|source| BuildError::InvalidManifest {
+        path: manifest.into(),
+        source,
+    })
+}
+
+/// Writes `value` as pretty-printed JSON to `manifest`, usually a
+/// `package.json`.
+pub(crate) fn write_manifest(manifest: &Path, value: &Value) -> Result<(), BuildError> {
+    let contents = serde_json::to_string_pretty(value).expect("JSON values always serialize");
+    fs::write(manifest, contents + "\n").map_err(|source| BuildError::CannotWriteManifest {
+        path: manifest.into(),
+        source,
+    })
+}
+
+/// Checks whether the packages installed in `dir/node_modules` match
+/// `dir/package-lock.json`.
+///
+/// npm 7 and later record the installed packages in the hidden lockfile
+/// `node_modules/.package-lock.json`. The installation is considered in sync
+/// if it lists the packages of the lockfile in the same versions, and no
+/// others. Packages marked `optional` in the lockfile may be missing, as
+/// npm skips optional packages for other platforms, such as the native
+/// binaries of esbuild or rollup. Lockfiles without a `packages` section,
+/// as written by npm 6, never are.
+pub(crate) fn node_modules_in_sync(dir: &Path) -> bool {
+    let packages = |path: &Path| match read_manifest(path).ok()?.get("packages") {
+        Some(Value::Object(packages)) => Some(
+            packages
+                .iter()
+                .filter(|(name, _)| !name.is_empty())
+                .map(|(name, package)| (name.clone(), package.clone()))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+        _ => None,
+    };
+    let (locked, installed) = match (
+        packages(&dir.join("package-lock.json")),
+        packages(&dir.join("node_modules").join(".package-lock.json")),
+    ) {
+        (Some(locked), Some(installed)) => (locked, installed),
+        _ => return false,
+    };
+    let all_locked = locked
+        .iter()
+        .all(|(name, package)| match installed.get(name) {
+            Some(installed) => installed.get("version") == package.get("version"),
+            None => package.get("optional") == Some(&Value::Bool(true)),
+        });
+    all_locked && installed.keys().all(|name| locked.contains_key(name))
+}
+
+/// Lists the direct dependencies of `dir/package.json` that are missing
+/// from `dir/package-lock.json`, or recorded there with a different version
+/// range, in alphabetical order.
+///
+/// Lockfiles written by npm 7 and later record the ranges of the root
+/// package, older ones only which packages are locked.
+pub(crate) fn lockfile_drift(dir: &Path) -> Result<Vec<String>, BuildError> {
+    const SECTIONS: [&str; 3] = ["dependencies", "devDependencies", "optionalDependencies"];
+    let manifest = read_manifest(&dir.join("package.json"))?;
+    let lockfile = read_manifest(&dir.join("package-lock.json"))?;
+    let root = lockfile.pointer("/packages/");
+    let mut drift = Vec::new();
+    for section in SECTIONS.iter() {
+        let dependencies = match manifest.get(section) {
+            Some(Value::Object(dependencies)) => dependencies,
+            _ => continue,
+        };
+        for (name, range) in dependencies {
+            let in_sync = match root {
+                Some(root) => {
+                    root.get(section).and_then(|locked| locked.get(name)) == Some(range)
+                        && lockfile["packages"]
+                            .get(format!("node_modules/{}", name))
+                            .is_some()
+                }
+                None => lockfile
+                    .get("dependencies")
+                    .and_then(|locked| locked.get(name))
+                    .is_some(),
+            };
+            if !in_sync {
+                drift.push(name.clone());
+            }
+        }
+    }
+    drift.sort();
+    drift.dedup();
+    Ok(drift)
+}
+
+/// Lists the packages locked in `dir/package-lock.json` as `name@version`,
+/// sorted and without duplicates.
+///
+/// Lockfiles written by npm 7 and later list every package under
+/// `packages`, keyed by its path in `node_modules`. Older ones only have
+/// `dependencies`, nested for packages installed below another package.
+/// The root package and linked workspaces, which have no version, are
+/// skipped.
+pub(crate) fn locked_packages(dir: &Path) -> Result<Vec<String>, BuildError> {
+    fn visit_v1(dependencies: &Value, packages: &mut Vec<String>) {
+        if let Value::Object(dependencies) = dependencies {
+            for (name, package) in dependencies {
+                if let Some(version) = package.get("version").and_then(Value::as_str) {
+                    packages.push(format!("{}@{}", name, version));
+                }
+                if let Some(nested) = package.get("dependencies") {
+                    visit_v1(nested, packages);
+                }
+            }
+        }
+    }
+
+    let lockfile = read_manifest(&dir.join("package-lock.json"))?;
+    let mut packages = Vec::new();
+    match lockfile.get("packages") {
+        Some(Value::Object(locked)) => {
+            for (path, package) in locked {
+                let name = match package.get("name").and_then(Value::as_str) {
+                    Some(name) => name,
+                    None => match path.rfind("node_modules/") {
+                        Some(start) => &path[start + "node_modules/".len()..],
+                        None => continue,
+                    },
+                };
+                if let Some(version) = package.get("version").and_then(Value::as_str) {
+                    if !path.is_empty() {
+                        packages.push(format!("{}@{}", name, version));
+                    }
+                }
+            }
+        }
+        _ => visit_v1(&lockfile["dependencies"], &mut packages),
+    }
+    packages.sort();
+    packages.dedup();
+    Ok(packages)
+}
+
+/// Reads the `name` of the package from the `package.json` at `manifest`.
+///
+/// Returns `None` if the manifest has no name, as is common for private
+/// projects.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid JSON.
+pub fn package_name(manifest: &Path) -> Result<Option<String>, BuildError> {
+    Ok(read_manifest(manifest)?
+        .get("name")
+        .and_then(Value::as_str)
+        .map(String::from))
+}
+
+/// Reads the package manager pinned with corepack's `packageManager` field,
+/// e.g. `pnpm@8.6.0`, from the `package.json` at `manifest`.
+///
+/// Returns `None` if the file cannot be read or the field is missing.
+pub(crate) fn package_manager(manifest: &Path) -> Option<String> {
+    read_manifest(manifest)
+        .ok()?
+        .get("packageManager")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// The dependency fields of a `package.json` considered when ordering
+/// workspaces.
+const DEPENDENCY_FIELDS: [&str; 4] = [
+    "dependencies",
+    "devDependencies",
+    "optionalDependencies",
+    "peerDependencies",
+];
+
+/// Lists the names of the workspaces of the project in `dir`, with every
+/// workspace after the workspaces it depends on.
+///
+/// Workspaces are found with the patterns in the `workspaces` field of
+/// `dir/package.json`, either a list or an object with a `packages` list as
+/// used by yarn. Workspaces without a name are skipped, as are patterns
+/// that are not valid globs.
+pub(crate) fn workspace_order(dir: &Path) -> Result<Vec<String>, BuildError> {
+    let root = read_manifest(&dir.join("package.json"))?;
+    let patterns = match root.get("workspaces") {
+        Some(Value::Object(workspaces)) => workspaces.get("packages"),
+        workspaces => workspaces,
+    };
+    let patterns: Vec<_> = match patterns {
+        Some(Value::Array(patterns)) => patterns.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let escaped = glob::Pattern::escape(&dir.to_string_lossy());
+    let mut packages: Vec<(String, Vec<String>)> = Vec::new();
+    for pattern in patterns {
+        let full = format!("{}/{}", escaped, pattern.trim_end_matches('/'));
+        let paths = match glob::glob(&full) {
+            Ok(paths) => paths,
+            Err(_) => continue,
+        };
+        for path in paths.flatten() {
+            let manifest = path.join("package.json");
+            if !manifest.is_file() {
+                continue;
+            }
+            let manifest = read_manifest(&manifest)?;
+            let name = match manifest.get("name").and_then(Value::as_str) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let dependencies = DEPENDENCY_FIELDS
+                .iter()
+                .filter_map(|field| manifest.get(field)?.as_object())
+                .flat_map(|dependencies| dependencies.keys().cloned())
+                .collect();
+            if !packages.iter().any(|(known, _)| *known == name) {
+                packages.push((name, dependencies));
+            }
+        }
+    }
+
+    let mut order = Vec::new();
+    for (name, _) in &packages {
+        visit_workspace(name, &packages, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Adds `name` to `order` after the workspaces it depends on, failing if
+/// it depends on one of the workspaces in `path`, which lead to it.
+fn visit_workspace(
+    name: &str,
+    packages: &[(String, Vec<String>)],
+    path: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<(), BuildError> {
+    if order.iter().any(|done| done == name) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|visiting| visiting == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name.into());
+        return Err(BuildError::DependencyCycle(cycle));
+    }
+    // Dependencies that are not workspaces come from the registry
+    let dependencies = match packages.iter().find(|(known, _)| known == name) {
+        Some((_, dependencies)) => dependencies,
+        None => return Ok(()),
+    };
+    path.push(name.into());
+    for dependency in dependencies {
+        visit_workspace(dependency, packages, path, order)?;
+    }
+    path.pop();
+    order.push(name.into());
+    Ok(())
+}
+
+/// Lists the names of the scripts defined in the `package.json` at
+/// `manifest`, in alphabetical order.
+///
+/// A manifest without a `scripts` object defines no scripts.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid JSON.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+///
+/// let scripts = npm_rs::scripts(Path::new("frontend/package.json")).unwrap();
+/// for script in scripts.iter().filter(|s| s.starts_with("build:")) {
+///     println!("{}", script);
+/// }
+/// ```
+pub fn scripts(manifest: &Path) -> Result<Vec<String>, BuildError> {
+    let manifest = read_manifest(manifest)?;
+    let mut scripts: Vec<_> = match manifest.get("scripts") {
+        Some(Value::Object(scripts)) => scripts.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    scripts.sort();
+    Ok(scripts)
+}
@@ -0,0 +1,65 @@
+use crate::BuildError;
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus};
+
+fn pty_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> BuildError {
+    BuildError::Pty(err.into())
+}
+
+#[cfg(unix)]
+fn exit_status(code: u32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code as i32) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: u32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code)
+}
+
+/// Runs `command` attached to a new pseudo terminal and copies everything
+/// it prints to `output`.
+///
+/// Only the program, arguments, environment and working directory of
+/// `command` are used, stdio settings are replaced by the terminal.
+pub(crate) fn status(command: &Command, output: &mut dyn Write) -> Result<ExitStatus, BuildError> {
+    let pair = native_pty_system()
+        .openpty(PtySize::default())
+        .map_err(pty_error)?;
+
+    let mut builder = CommandBuilder::new(command.get_program());
+    builder.args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => builder.env(key, value),
+            None => builder.env_remove(key),
+        }
+    }
+    if let Some(dir) = command.get_current_dir() {
+        if !dir.as_os_str().is_empty() {
+            builder.cwd(dir);
+        }
+    }
+
+    let mut child = pair.slave.spawn_command(builder).map_err(pty_error)?;
+    // The reader only sees the end of the output once every handle to the
+    // slave side is closed.
+    drop(pair.slave);
+
+    let mut reader = pair.master.try_clone_reader().map_err(pty_error)?;
+    let mut buffer = [0; 4096];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => output.write_all(&buffer[..n])?,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            // Linux reports EIO instead of end of file once the child exits
+            Err(_) => break,
+        }
+    }
+
+    let status = child.wait().map_err(BuildError::SpawnFailed)?;
+    Ok(exit_status(status.exit_code()))
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A summary of the npm commands run by a [`Build`]
+///
+/// Obtained from [`Build::report`]. With the `serde` feature enabled this
+/// type implements `Serialize`, and [`Build::report_json`] renders it as
+/// JSON. Paths that are not valid UTF-8 are serialized lossily, with
+/// invalid sequences replaced by `U+FFFD`.
+///
+/// [`Build`]: struct.Build.html
+/// [`Build::report`]: struct.Build.html#method.report
+/// [`Build::report_json`]: struct.Build.html#method.report_json
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RunReport {
+    /// The name of the package in the project directory, if it has one
+    pub package: Option<String>,
+    /// The package manager used to run the commands
+    pub package_manager: String,
+    /// The path of the npm executable, if it was resolved
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_optional_path"))]
+    pub npm_path: Option<PathBuf>,
+    /// Every command run, in order
+    pub commands: Vec<CommandReport>,
+    /// The outputs declared with [`Build::use_temp_target`] and
+    /// [`Build::copy_outputs_to`], and the files matching
+    /// [`Build::expect_outputs_glob`], relative to the directory npm is run
+    /// in
+    ///
+    /// [`Build::use_temp_target`]: struct.Build.html#method.use_temp_target
+    /// [`Build::copy_outputs_to`]: struct.Build.html#method.copy_outputs_to
+    /// [`Build::expect_outputs_glob`]: struct.Build.html#method.expect_outputs_glob
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_paths"))]
+    pub outputs: Vec<PathBuf>,
+    /// How often the last [`Build::execute`] or [`Build::try_run_script`]
+    /// tried to run its scripts, more than 1 if [`Build::retry`] retried,
+    /// 0 if neither was called
+    ///
+    /// [`Build::execute`]: struct.Build.html#method.execute
+    /// [`Build::try_run_script`]: struct.Build.html#method.try_run_script
+    /// [`Build::retry`]: struct.Build.html#method.retry
+    pub attempts: u32,
+    /// The outcome of every target registered with [`Build::add_target`],
+    /// in order, from the last [`Build::execute`] that built targets
+    ///
+    /// [`Build::add_target`]: struct.Build.html#method.add_target
+    /// [`Build::execute`]: struct.Build.html#method.execute
+    pub targets: Vec<TargetReport>,
+}
+
+/// A single npm command in a [`RunReport`]
+///
+/// [`RunReport`]: struct.RunReport.html
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommandReport {
+    /// The arguments passed to npm, e.g. `["run", "build"]`
+    pub args: Vec<String>,
+    /// The value of `NODE_ENV` for the command
+    pub node_env: String,
+    /// How long the command took
+    pub duration: Duration,
+    /// The exit code of npm, or `None` if it was terminated by a signal
+    pub exit_code: Option<i32>,
+}
+
+/// The outcome of a single [`Target`] in a [`RunReport`]
+///
+/// [`Target`]: struct.Target.html
+/// [`RunReport`]: struct.RunReport.html
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TargetReport {
+    /// The name of the target
+    pub name: String,
+    /// The output directory of the target
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_path"))]
+    pub directory: PathBuf,
+    /// Why building the target failed, or `None` if it succeeded
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_path<S: serde::Serializer>(
+    path: &std::path::Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+#[cfg(feature = "serde")]
+fn serialize_paths<S: serde::Serializer>(
+    paths: &[PathBuf],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| path.to_string_lossy()))
+}
+
+#[cfg(feature = "serde")]
+fn serialize_optional_path<S: serde::Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serialize_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
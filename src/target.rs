@@ -0,0 +1,16 @@
+/// A named build of the project, registered with [`Build::add_target`]
+///
+/// Every target runs its own script with its own environment variables,
+/// sharing the files and node packages installed for the [`Build`].
+///
+/// [`Build`]: struct.Build.html
+/// [`Build::add_target`]: struct.Build.html#method.add_target
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+    /// The name of the target, also the name of its output directory
+    pub name: String,
+    /// The npm script building the target
+    pub script: String,
+    /// Environment variables set only for this target's script
+    pub env: Vec<(String, String)>,
+}